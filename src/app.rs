@@ -1,67 +1,492 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
 use std::io::{self};
+use std::sync::mpsc::Receiver;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
+use chrono::Local;
 use crossterm::event::{self, Event as CEvent, KeyCode, KeyEventKind};
 
 use ratatui::{prelude::*, widgets::*};
 use tui_input::backend::crossterm::EventHandler;
 use tui_input::Input;
 
-use crate::commands::{get_all_packages, PackageType, PackageVersionInfo};
-use crate::config::{Config, ConfigFile};
+use crate::backend::{self, PackageBackend};
+use crate::cache::DetailsCache;
+use crate::commands::{
+    format_date, format_size, PackageDetails, PackageType, PackageUpdate, PackageVersionInfo,
+    SyncPackage,
+};
+use crate::config::{self, Column, Config, ConfigFile};
+use crate::files::{self, FileRow};
+use crate::graph::{DependencyGraph, TreeRow};
+use crate::history::{self, EventKind, HistoryEvent, PastTransaction};
+use crate::pkgcache::{self, CacheGroup, CachedPackage, PrunePolicy};
+use crate::query::{Query, Target};
+use crate::runner::{self, CommandOutput};
 use crate::ui::*;
+use crate::version::{self, Version};
+use crate::watcher::DatabaseWatcher;
+use crate::worker::{Job, JobResult, Preview, Worker};
 
-// TODO: Should the search be separate from other filters? Allowing for subsection filtering.
-// eg: Explicit with a certain name.
-#[derive(Clone)]
+/// One term of a list's filter chain, a package has to match every term to be shown. E.g.
+/// `Foreign` and `Search("python")`, or `Orphans` and `Search("size:>50M")`.
+#[derive(Clone, PartialEq)]
 pub enum ListFilter {
-    All,
     Explicit,
+    Dependencies,
     Orphans,
     Foreign,
+    /// Packages built from a version control checkout, e.g. `-git` packages.
+    Vcs,
+    /// Packages with an epoch in their version.
+    Epoch,
+    /// A search typed in the filter popup, see `Query`.
     Search(String),
 }
 
-// TODO: Turn into a generic??
-pub struct StatefulList {
+impl ListFilter {
+    /// Whether the term picks packages by install reason, see `add_filter`.
+    fn is_package_type(&self) -> bool {
+        matches!(
+            self,
+            ListFilter::Explicit
+                | ListFilter::Dependencies
+                | ListFilter::Orphans
+                | ListFilter::Foreign
+        )
+    }
+
+    /// How the term is shown in the list title.
+    pub fn label(&self) -> String {
+        match self {
+            ListFilter::Explicit => "explicit".to_string(),
+            ListFilter::Dependencies => "dependencies".to_string(),
+            ListFilter::Orphans => "orphans".to_string(),
+            ListFilter::Foreign => "foreign".to_string(),
+            ListFilter::Vcs => "vcs".to_string(),
+            ListFilter::Epoch => "epoch".to_string(),
+            ListFilter::Search(s) => format!("\"{}\"", s),
+        }
+    }
+}
+
+/// Compares two sort keys, with the missing ones last whichever way round the order is.
+fn compare_known<T: Ord>(a: Option<T>, b: Option<T>, descending: bool) -> std::cmp::Ordering {
+    match (a, b) {
+        (Some(a), Some(b)) if descending => b.cmp(&a),
+        (Some(a), Some(b)) => a.cmp(&b),
+        (Some(_), None) => std::cmp::Ordering::Less,
+        (None, Some(_)) => std::cmp::Ordering::Greater,
+        (None, None) => std::cmp::Ordering::Equal,
+    }
+}
+
+/// The queries of the searches in `filters` that parse.
+pub fn search_queries(filters: &[ListFilter]) -> Vec<Query> {
+    filters
+        .iter()
+        .filter_map(|f| match f {
+            ListFilter::Search(s) => Query::parse(s).ok(),
+            _ => None,
+        })
+        .collect()
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum SortOrder {
+    Name,
+    Version,
+    InstalledSize,
+    InstallDate,
+    BuildDate,
+    /// From pacman's log, see `PackageBackend::history`.
+    LastUpgraded,
+    /// How many installed packages require the package.
+    RequiredBy,
+    Repo,
+}
+
+impl SortOrder {
+    /// In the order they're listed in the sort menu.
+    pub const ALL: [SortOrder; 8] = [
+        SortOrder::Name,
+        SortOrder::Version,
+        SortOrder::InstalledSize,
+        SortOrder::InstallDate,
+        SortOrder::BuildDate,
+        SortOrder::LastUpgraded,
+        SortOrder::RequiredBy,
+        SortOrder::Repo,
+    ];
+
+    pub fn label(&self) -> &str {
+        match self {
+            SortOrder::Name => "name",
+            SortOrder::Version => "version",
+            SortOrder::InstalledSize => "installed size",
+            SortOrder::InstallDate => "install date",
+            SortOrder::BuildDate => "build date",
+            SortOrder::LastUpgraded => "last upgraded",
+            SortOrder::RequiredBy => "required by count",
+            SortOrder::Repo => "repo",
+        }
+    }
+
+    /// Sizes, dates and counts start with the biggest or newest, the rest alphabetically.
+    fn descending_by_default(&self) -> bool {
+        !matches!(self, SortOrder::Name | SortOrder::Version | SortOrder::Repo)
+    }
+
+    /// Whether the order comes from the package details.
+    fn needs_details(&self) -> bool {
+        matches!(
+            self,
+            SortOrder::InstalledSize
+                | SortOrder::InstallDate
+                | SortOrder::BuildDate
+                | SortOrder::RequiredBy
+        )
+    }
+}
+
+/// Which events the history tab shows.
+#[derive(Clone, Copy, PartialEq)]
+pub enum HistoryKind {
+    All,
+    /// Anything that changed a package.
+    Packages,
+    Installed,
+    Upgraded,
+    Downgraded,
+    Removed,
+    Reinstalled,
+    /// Commands and transactions starting, completing or failing.
+    Transactions,
+    Warnings,
+    Hooks,
+}
+
+impl HistoryKind {
+    const ALL: [HistoryKind; 10] = [
+        HistoryKind::All,
+        HistoryKind::Packages,
+        HistoryKind::Installed,
+        HistoryKind::Upgraded,
+        HistoryKind::Downgraded,
+        HistoryKind::Removed,
+        HistoryKind::Reinstalled,
+        HistoryKind::Transactions,
+        HistoryKind::Warnings,
+        HistoryKind::Hooks,
+    ];
+
+    fn next(self) -> HistoryKind {
+        let index = Self::ALL
+            .iter()
+            .position(|k| *k == self)
+            .unwrap_or_default();
+        Self::ALL[(index + 1) % Self::ALL.len()]
+    }
+
+    fn previous(self) -> HistoryKind {
+        let index = Self::ALL
+            .iter()
+            .position(|k| *k == self)
+            .unwrap_or_default();
+        Self::ALL[(index + Self::ALL.len() - 1) % Self::ALL.len()]
+    }
+
+    pub fn label(&self) -> &str {
+        match self {
+            HistoryKind::All => "everything",
+            HistoryKind::Packages => "package changes",
+            HistoryKind::Installed => "installs",
+            HistoryKind::Upgraded => "upgrades",
+            HistoryKind::Downgraded => "downgrades",
+            HistoryKind::Removed => "removals",
+            HistoryKind::Reinstalled => "reinstalls",
+            HistoryKind::Transactions => "transactions",
+            HistoryKind::Warnings => "warnings",
+            HistoryKind::Hooks => "hooks",
+        }
+    }
+
+    fn matches(&self, kind: &EventKind) -> bool {
+        match self {
+            HistoryKind::All => true,
+            HistoryKind::Packages => matches!(
+                kind,
+                EventKind::Installed { .. }
+                    | EventKind::Upgraded { .. }
+                    | EventKind::Downgraded { .. }
+                    | EventKind::Removed { .. }
+                    | EventKind::Reinstalled { .. }
+            ),
+            HistoryKind::Installed => matches!(kind, EventKind::Installed { .. }),
+            HistoryKind::Upgraded => matches!(kind, EventKind::Upgraded { .. }),
+            HistoryKind::Downgraded => matches!(kind, EventKind::Downgraded { .. }),
+            HistoryKind::Removed => matches!(kind, EventKind::Removed { .. }),
+            HistoryKind::Reinstalled => matches!(kind, EventKind::Reinstalled { .. }),
+            HistoryKind::Transactions => matches!(
+                kind,
+                EventKind::TransactionStarted
+                    | EventKind::TransactionCompleted
+                    | EventKind::TransactionFailed
+                    | EventKind::Command(_)
+            ),
+            HistoryKind::Warnings => matches!(kind, EventKind::Warning(_)),
+            HistoryKind::Hooks => matches!(kind, EventKind::Hook(_)),
+        }
+    }
+}
+
+pub struct StatefulList<T> {
     pub state: ListState,
-    pub items: Vec<PackageVersionInfo>,
-    pub filtered_items: Vec<PackageVersionInfo>,
+    pub items: Vec<T>,
+    pub filtered_items: Vec<T>,
     pub last_selected: Option<usize>,
-    pub list_filter: ListFilter,
+    /// Every term has to match, an empty chain shows everything.
+    pub filters: Vec<ListFilter>,
+    /// Names of the packages marked for an action.
+    pub marked: HashSet<String>,
 }
 
 #[derive(PartialEq)]
 pub enum Screens {
     DetailsList,
     FilterInput,
+    ConfirmTransaction,
+    CommandLog,
+    OrphanCleanup,
+    DependencyTree,
+    SortMenu,
+    ColumnMenu,
+    Downgrade,
+    Files,
+    OwnerInput,
+    Help,
+}
+
+/// State of the "clean orphans" workflow. It stays around while the removal runs so any orphans
+/// left behind by it can be offered next.
+pub struct OrphanCleanup {
+    /// The orphans with their installed size in bytes.
+    pub orphans: Vec<(String, u64)>,
+    /// Orphans the user unticked, these are never offered again.
+    pub keep: HashSet<String>,
+    pub state: ListState,
+}
+
+/// State of the dependency tree popup.
+pub struct DependencyTree {
+    pub root: String,
+    /// Show the packages requiring `root` instead of its dependencies.
+    pub reverse: bool,
+    /// Paths of the expanded rows, see `TreeRow::path`.
+    pub expanded: HashSet<Vec<String>>,
+    pub rows: Vec<TreeRow>,
+    pub state: ListState,
+}
+
+/// State of the files popup.
+pub struct FileList {
+    pub package: String,
+    /// Empty until they're laid out on the worker, see `Job::FileTree`.
+    pub rows: Vec<FileRow>,
+    pub loading: bool,
+    pub state: ListState,
+}
+
+/// State of the downgrade popup.
+pub struct Downgrade {
+    pub name: String,
+    pub installed: Version,
+    /// Every cached version of the package, newest first.
+    pub packages: Vec<CachedPackage>,
+    /// Add the package to IgnorePkg once it's installed.
+    pub ignore: bool,
+    pub state: ListState,
+}
+
+/// Something waiting for the package cache to be scanned, see `App::with_cache`.
+pub enum CacheAction {
+    Rollback(PastTransaction),
+    Downgrade { name: String, installed: Version },
 }
+
+/// A command waiting for the user to confirm it.
+pub struct Transaction {
+    /// Matches the transaction up with its preview, see `Job::Preview`.
+    pub id: usize,
+    pub title: String,
+    pub command: Vec<String>,
+    /// Anything else worth knowing before running the command.
+    pub notes: Vec<String>,
+    /// Run once `command` succeeds.
+    pub then: Option<Vec<String>>,
+    /// The packages that were picked for the command.
+    pub targets: Vec<String>,
+    /// The packages the command would touch, or why it can't run. `None` until the preview is
+    /// done.
+    pub packages: Option<Result<Vec<String>, String>>,
+}
+
 pub struct App {
-    pub packages_list: StatefulList,
+    pub packages_list: StatefulList<PackageVersionInfo>,
+    /// Packages from the sync repositories, loaded the first time the tab is opened.
+    pub sync_list: StatefulList<SyncPackage>,
+    /// Installed packages with a newer version in `sync_list`.
+    pub updates_list: StatefulList<PackageUpdate>,
+    pub active_menu_item: MenuItem,
+    pub sort_order: SortOrder,
+    pub sort_descending: bool,
+    /// The highlighted entry of the sort menu.
+    pub sort_menu: ListState,
     pub current_screen: Screens,
     pub filter_input: Input,
+    /// The filters to go back to if the search is cancelled, the search is applied while typing.
+    filters_before_search: Option<Vec<ListFilter>>,
     pub config: Config,
+    pub backend: Arc<dyn PackageBackend>,
+    pub worker: Worker,
+    pub loading_packages: bool,
+    pub loading_sync_packages: bool,
+    pub details_cache: DetailsCache,
+    /// Packages whose details were asked for but haven't arrived yet.
+    pub pending_details: HashSet<String>,
+    pub loading_graph: bool,
+    /// The files of every installed package, only loaded once a search needs them.
+    pub files: Option<HashMap<String, Vec<String>>>,
+    pub loading_files: bool,
+    /// Every event in the package manager's log, newest first.
+    pub history_list: StatefulList<HistoryEvent>,
+    pub history_kind: HistoryKind,
+    /// The package changes of `history_list` grouped by transaction, newest first.
+    pub history_transactions: StatefulList<PastTransaction>,
+    /// Show `history_transactions` instead of the events.
+    pub show_transactions: bool,
+    pub history_error: Option<String>,
+    pub loading_history: bool,
+    /// The package cache grouped by package, loaded the first time the tab is opened.
+    pub cache_list: StatefulList<CacheGroup>,
+    pub loading_cache: bool,
+    /// Run once the scan `loading_cache` is waiting for is done.
+    cache_action: Option<CacheAction>,
+    /// How many versions of each package pruning keeps.
+    pub cache_keep: usize,
+    /// When each package was last upgraded, from the history.
+    pub last_upgrades: HashMap<String, i64>,
+    /// `None` if the database can't be watched, then only `r` reloads it.
+    watcher: Option<DatabaseWatcher>,
+    /// When the database was last seen changing, see `check_database_changes`.
+    database_changed: Option<Instant>,
+    transaction_count: usize,
+    pub transaction: Option<Transaction>,
+    /// Output of the last command that was run.
+    pub command_log: Vec<String>,
+    pub command_output: Option<Receiver<CommandOutput>>,
+    /// The `Transaction::then` of the running command.
+    next_command: Option<Vec<String>>,
+    pub orphan_cleanup: Option<OrphanCleanup>,
+    /// Built the first time it's needed and thrown away whenever the packages are reloaded.
+    pub dependency_graph: Option<DependencyGraph>,
+    pub dependency_tree: Option<DependencyTree>,
+    pub downgrade: Option<Downgrade>,
+    pub file_list: Option<FileList>,
+    /// What's typed in the "who owns this path?" prompt.
+    pub owner_input: Input,
+    /// A path to look up the owner of once the files are loaded.
+    pending_owner: Option<String>,
+    /// Whether the package list takes the whole width, hiding the details.
+    pub zoomed: bool,
+    /// The highlighted entry of the column menu, an index into `Config::columns`.
+    pub column_menu: ListState,
+    /// Feedback from the last action, shown in the footer until the next key press.
+    pub message: Option<String>,
 }
 
 impl App {
-    pub fn new() -> Self {
-        Self {
-            packages_list: StatefulList::new(),
+    /// `backend_name` overrides the backend set in the config file.
+    pub fn new(backend_name: Option<String>) -> Result<Self, String> {
+        // Try loading the config file, if there is an issue fallback on the hardcoded default.
+        let config =
+            ConfigFile::parse(confy::load("pacmanman", None).unwrap_or(ConfigFile::default()))
+                .unwrap_or(ConfigFile::parse(ConfigFile::default()).unwrap());
+
+        let backend_name = backend_name.unwrap_or(config.backend.clone());
+        let backend: Arc<dyn PackageBackend> = backend::from_name(&backend_name, &config)
+            .ok_or(format!("Unknown package manager backend: {}", backend_name))?
+            .into();
+
+        let watcher = DatabaseWatcher::new(&backend.database_paths()).ok();
+        let details_cache = DetailsCache::new(config.details_cache_path.clone());
+
+        // The packages are loaded in the background so the first frame shows up straight away.
+        let worker = Worker::spawn(backend.clone());
+        worker.send(Job::Packages);
+
+        Ok(Self {
+            packages_list: StatefulList::with_items(vec![]),
+            sync_list: StatefulList::with_items(vec![]),
+            updates_list: StatefulList::with_items(vec![]),
+            active_menu_item: MenuItem::PackageList,
+            sort_order: SortOrder::Name,
+            sort_descending: false,
+            sort_menu: ListState::default(),
             current_screen: Screens::DetailsList,
             filter_input: Input::default(),
-            // Try loading the config file, if there is an issue fallback on the hardcoded default.
-            config: ConfigFile::parse(
-                confy::load("pacmanman", None).unwrap_or(ConfigFile::default()),
-            )
-            .unwrap_or(ConfigFile::parse(ConfigFile::default()).unwrap()),
-        }
+            filters_before_search: None,
+            config,
+            backend,
+            worker,
+            loading_packages: true,
+            loading_sync_packages: false,
+            details_cache,
+            pending_details: HashSet::new(),
+            loading_graph: false,
+            files: None,
+            loading_files: false,
+            history_list: StatefulList::with_items(vec![]),
+            history_kind: HistoryKind::All,
+            history_transactions: StatefulList::with_items(vec![]),
+            show_transactions: false,
+            history_error: None,
+            loading_history: false,
+            cache_list: StatefulList::with_items(vec![]),
+            loading_cache: false,
+            cache_action: None,
+            // The same as paccache.
+            cache_keep: 3,
+            last_upgrades: HashMap::new(),
+            watcher,
+            database_changed: None,
+            transaction_count: 0,
+            transaction: None,
+            command_log: vec![],
+            command_output: None,
+            next_command: None,
+            orphan_cleanup: None,
+            dependency_graph: None,
+            dependency_tree: None,
+            downgrade: None,
+            file_list: None,
+            owner_input: Input::default(),
+            pending_owner: None,
+            zoomed: false,
+            column_menu: ListState::default(),
+            message: None,
+        })
     }
 
     pub fn run(&mut self, mut terminal: Terminal<impl Backend>) -> io::Result<()> {
-        let menu_titles = vec!["Packages", "Quit"];
-        let mut active_menu_item = MenuItem::PackageList;
+        let menu_titles = vec!["Packages", "Sync", "Updates", "History", "Cache", "Quit"];
 
         // Render loop
         loop {
+            self.prefetch_details();
+
             terminal
                 .draw(|frame| {
                     let size = frame.size();
@@ -80,16 +505,64 @@ impl App {
 
                     let menu = create_menu(&menu_titles);
 
-                    render_tabs(menu, active_menu_item, frame, chunks[0]);
+                    render_tabs(menu, self.active_menu_item, frame, chunks[0]);
 
-                    render_footer(frame, chunks[2]);
+                    render_footer(frame, chunks[2], self.message.as_deref());
 
-                    match active_menu_item {
+                    match self.active_menu_item {
                         MenuItem::PackageList => {
-                            if self.packages_list.filtered_items.len() > 0 {
+                            if !self.packages_list.filtered_items.is_empty() {
                                 self.render_package_details(frame, chunks[1]);
+                            } else if self.loading_packages {
+                                render_loading(frame, chunks[1], "Loading packages...");
+                            } else {
+                                render_empty_list(
+                                    frame,
+                                    chunks[1],
+                                    &self.packages_list.filters,
+                                    MenuItem::PackageList,
+                                );
+                            }
+                        }
+                        MenuItem::SyncList => {
+                            if !self.sync_list.filtered_items.is_empty() {
+                                self.render_sync_details(frame, chunks[1]);
+                            } else if self.loading_sync_packages {
+                                render_loading(frame, chunks[1], "Loading sync packages...");
                             } else {
-                                render_empty_list(frame, chunks[1]);
+                                render_empty_list(
+                                    frame,
+                                    chunks[1],
+                                    &self.sync_list.filters,
+                                    MenuItem::SyncList,
+                                );
+                            }
+                        }
+                        MenuItem::Updates => {
+                            if !self.updates_list.filtered_items.is_empty() {
+                                self.render_updates(frame, chunks[1]);
+                            } else if self.loading_sync_packages {
+                                render_loading(frame, chunks[1], "Looking for updates...");
+                            } else {
+                                render_no_updates(frame, chunks[1]);
+                            }
+                        }
+                        MenuItem::History => {
+                            if let Some(error) = &self.history_error {
+                                render_loading(frame, chunks[1], error);
+                            } else if self.history_list.items.is_empty() && self.loading_history {
+                                render_loading(frame, chunks[1], "Loading history...");
+                            } else {
+                                self.render_history(frame, chunks[1]);
+                            }
+                        }
+                        MenuItem::Cache => {
+                            if !self.cache_list.items.is_empty() {
+                                self.render_cache(frame, chunks[1]);
+                            } else if self.loading_cache {
+                                render_loading(frame, chunks[1], "Scanning the package cache...");
+                            } else {
+                                render_loading(frame, chunks[1], "The package cache is empty.");
                             }
                         }
                     }
@@ -97,46 +570,212 @@ impl App {
                     // Render any pop up screens after everything else has been rendered.
                     match self.current_screen {
                         Screens::FilterInput => self.render_filter_popup(frame),
+                        Screens::ConfirmTransaction => self.render_transaction_popup(frame),
+                        Screens::CommandLog => self.render_command_log(frame),
+                        Screens::OrphanCleanup => self.render_orphan_cleanup(frame),
+                        Screens::DependencyTree => self.render_dependency_tree(frame),
+                        Screens::SortMenu => self.render_sort_menu(frame),
+                        Screens::ColumnMenu => self.render_column_menu(frame),
+                        Screens::Downgrade => self.render_downgrade(frame),
+                        Screens::Files => self.render_file_list(frame),
+                        Screens::OwnerInput => self.render_owner_popup(frame),
+                        Screens::Help => render_help_popup(frame, self.active_menu_item),
                         Screens::DetailsList => {}
                     }
                 })
                 .unwrap();
 
+            self.read_command_output();
+            self.read_job_results();
+            self.check_database_changes();
+
+            // Don't block on input so the output of a running command keeps being drawn.
+            if !event::poll(Duration::from_millis(100))? {
+                continue;
+            }
+
             // Input handling
             if let CEvent::Key(key) = event::read().unwrap() {
                 if key.kind == event::KeyEventKind::Release {
                     continue;
                 }
+                self.message = None;
                 match self.current_screen {
                     Screens::DetailsList => match key.code {
-                        KeyCode::Char('q') => return Ok(()),
-                        KeyCode::Char('p') => active_menu_item = MenuItem::PackageList,
-                        KeyCode::Up | KeyCode::Char('k') => self.packages_list.previous(),
-                        KeyCode::Down | KeyCode::Char('j') => self.packages_list.next(),
-                        KeyCode::Char('g') => self.go_top(),
-                        KeyCode::Char('G') => self.go_bottom(),
-                        KeyCode::Char('a') => self.change_filter(ListFilter::All),
-                        KeyCode::Char('e') => self.change_filter(ListFilter::Explicit),
-                        KeyCode::Char('o') => self.change_filter(ListFilter::Orphans),
-                        KeyCode::Char('f') => self.change_filter(ListFilter::Foreign),
-                        KeyCode::Char('s') => self.current_screen = Screens::FilterInput,
-                        _ => {}
+                        KeyCode::Char('q') => {
+                            // Not being able to cache the details isn't worth failing over.
+                            let _ = self.details_cache.save();
+                            return Ok(());
+                        }
+                        KeyCode::Char('p') | KeyCode::Char('P') => {
+                            self.switch_menu_item(MenuItem::PackageList)
+                        }
+                        KeyCode::Char('S') => self.switch_menu_item(MenuItem::SyncList),
+                        KeyCode::Char('U') => self.switch_menu_item(MenuItem::Updates),
+                        KeyCode::Char('H') => self.switch_menu_item(MenuItem::History),
+                        KeyCode::Char('C') => self.switch_menu_item(MenuItem::Cache),
+                        KeyCode::Char('W') => self.current_screen = Screens::OwnerInput,
+                        KeyCode::Tab => self.switch_menu_item(self.active_menu_item.next()),
+                        KeyCode::BackTab => self.switch_menu_item(self.active_menu_item.previous()),
+                        KeyCode::Char('?') => self.current_screen = Screens::Help,
+                        KeyCode::Char('r') => {
+                            self.refresh();
+                            self.message = Some("Reloading the packages...".to_string());
+                        }
+                        _ => match self.active_menu_item {
+                            MenuItem::PackageList => self.handle_package_list_key(key.code),
+                            MenuItem::SyncList => self.handle_sync_list_key(key.code),
+                            MenuItem::Updates => self.handle_updates_key(key.code),
+                            MenuItem::History => self.handle_history_key(key.code),
+                            MenuItem::Cache => self.handle_cache_key(key.code),
+                        },
                     },
                     Screens::FilterInput if key.kind == KeyEventKind::Press => match key.code {
                         KeyCode::Enter => {
-                            self.change_filter(ListFilter::Search(
-                                self.filter_input.value().to_string(),
-                            ));
+                            // An empty search doesn't add a term.
+                            if self.filter_input.value().is_empty() {
+                                if let Some(filters) = self.filters_before_search.clone() {
+                                    self.set_search_filters(filters);
+                                }
+                            }
+                            self.filters_before_search = None;
                             self.filter_input.reset();
                             self.current_screen = Screens::DetailsList;
                         }
                         KeyCode::Esc => {
+                            if let Some(filters) = self.filters_before_search.take() {
+                                self.set_search_filters(filters);
+                            }
                             self.filter_input.reset();
                             self.current_screen = Screens::DetailsList;
                         }
+                        KeyCode::Tab if self.active_menu_item != MenuItem::History => {
+                            self.config.rank_search_by_score = !self.config.rank_search_by_score;
+                            self.search();
+                        }
                         _ => {
                             self.filter_input.handle_event(&CEvent::Key(key));
+                            self.search();
+                        }
+                    },
+                    Screens::ConfirmTransaction => match key.code {
+                        KeyCode::Char('y') | KeyCode::Enter => {
+                            self.run_transaction(&mut terminal)?
+                        }
+                        KeyCode::Char('n') | KeyCode::Esc => {
+                            self.transaction = None;
+                            self.orphan_cleanup = None;
+                            self.current_screen = Screens::DetailsList;
+                        }
+                        _ => {}
+                    },
+                    // Only let the log be closed once the command has finished.
+                    Screens::CommandLog if self.command_output.is_none() => match key.code {
+                        KeyCode::Char('q') | KeyCode::Esc | KeyCode::Enter => {
+                            self.current_screen = Screens::DetailsList;
+                            if self.orphan_cleanup.is_some() {
+                                self.continue_orphan_cleanup();
+                            }
+                        }
+                        _ => {}
+                    },
+                    Screens::SortMenu => match key.code {
+                        KeyCode::Up | KeyCode::Char('k') => self.move_sort_menu_selection(-1),
+                        KeyCode::Down | KeyCode::Char('j') => self.move_sort_menu_selection(1),
+                        KeyCode::Enter | KeyCode::Char(' ') => self.pick_sort_order(),
+                        KeyCode::Esc | KeyCode::Char('q') | KeyCode::Char('O') => {
+                            self.current_screen = Screens::DetailsList
+                        }
+                        _ => {}
+                    },
+                    Screens::ColumnMenu => match key.code {
+                        KeyCode::Up | KeyCode::Char('k') => self.move_column_menu_selection(-1),
+                        KeyCode::Down | KeyCode::Char('j') => self.move_column_menu_selection(1),
+                        KeyCode::Char(' ') => self.toggle_column(),
+                        KeyCode::Char('K') => self.move_column(-1),
+                        KeyCode::Char('J') => self.move_column(1),
+                        KeyCode::Left | KeyCode::Char('h') => self.resize_column(-1),
+                        KeyCode::Right | KeyCode::Char('l') => self.resize_column(1),
+                        KeyCode::Esc | KeyCode::Enter | KeyCode::Char('q') => {
+                            self.current_screen = Screens::DetailsList
+                        }
+                        _ => {}
+                    },
+                    Screens::OwnerInput if key.kind == KeyEventKind::Press => match key.code {
+                        KeyCode::Enter => {
+                            let path = self.owner_input.value().trim().to_string();
+                            self.owner_input.reset();
+                            self.current_screen = Screens::DetailsList;
+                            if !path.is_empty() {
+                                self.pending_owner = Some(path);
+                                self.find_owner();
+                            }
+                        }
+                        KeyCode::Esc => {
+                            self.owner_input.reset();
+                            self.current_screen = Screens::DetailsList;
+                        }
+                        _ => {
+                            self.owner_input.handle_event(&CEvent::Key(key));
+                        }
+                    },
+                    Screens::Files => match key.code {
+                        KeyCode::Up | KeyCode::Char('k') => self.move_file_selection(-1),
+                        KeyCode::Down | KeyCode::Char('j') => self.move_file_selection(1),
+                        KeyCode::Char('g') => self.move_file_selection(isize::MIN),
+                        KeyCode::Char('G') => self.move_file_selection(isize::MAX),
+                        KeyCode::Esc | KeyCode::Char('q') => {
+                            self.file_list = None;
+                            self.current_screen = Screens::DetailsList;
+                        }
+                        _ => {}
+                    },
+                    Screens::Downgrade => match key.code {
+                        KeyCode::Up | KeyCode::Char('k') => self.move_downgrade_selection(-1),
+                        KeyCode::Down | KeyCode::Char('j') => self.move_downgrade_selection(1),
+                        KeyCode::Char('i') => {
+                            if let Some(downgrade) = self.downgrade.as_mut() {
+                                downgrade.ignore = !downgrade.ignore;
+                            }
+                        }
+                        KeyCode::Enter => self.confirm_downgrade(),
+                        KeyCode::Esc | KeyCode::Char('q') => {
+                            self.downgrade = None;
+                            self.current_screen = Screens::DetailsList;
+                        }
+                        _ => {}
+                    },
+                    Screens::Help => match key.code {
+                        KeyCode::Char('q') | KeyCode::Esc | KeyCode::Char('?') => {
+                            self.current_screen = Screens::DetailsList
+                        }
+                        _ => {}
+                    },
+                    Screens::OrphanCleanup => match key.code {
+                        KeyCode::Up | KeyCode::Char('k') => self.move_orphan_selection(-1),
+                        KeyCode::Down | KeyCode::Char('j') => self.move_orphan_selection(1),
+                        KeyCode::Char(' ') => self.toggle_orphan(),
+                        KeyCode::Enter => self.confirm_orphan_cleanup(),
+                        KeyCode::Esc | KeyCode::Char('q') => {
+                            self.orphan_cleanup = None;
+                            self.current_screen = Screens::DetailsList;
+                        }
+                        _ => {}
+                    },
+                    Screens::DependencyTree => match key.code {
+                        KeyCode::Up | KeyCode::Char('k') => self.move_tree_selection(-1),
+                        KeyCode::Down | KeyCode::Char('j') => self.move_tree_selection(1),
+                        KeyCode::Right | KeyCode::Char('l') | KeyCode::Char(' ') => {
+                            self.expand_tree_row(true)
                         }
+                        KeyCode::Left | KeyCode::Char('h') => self.expand_tree_row(false),
+                        KeyCode::Char('r') => self.reverse_tree(),
+                        KeyCode::Enter => self.jump_to_tree_row(),
+                        KeyCode::Esc | KeyCode::Char('q') => {
+                            self.dependency_tree = None;
+                            self.current_screen = Screens::DetailsList;
+                        }
+                        _ => {}
                     },
                     _ => {}
                 }
@@ -144,64 +783,1829 @@ impl App {
         }
     }
 
-    fn change_filter(&mut self, filter: ListFilter) {
-        self.packages_list.list_filter = filter;
-        self.packages_list.filtered_items = self
-            .packages_list
+    fn handle_package_list_key(&mut self, key: KeyCode) {
+        match key {
+            KeyCode::Up | KeyCode::Char('k') => self.packages_list.previous(),
+            KeyCode::Down | KeyCode::Char('j') => self.packages_list.next(),
+            KeyCode::Char('g') => self.packages_list.go_top(),
+            KeyCode::Char('G') => self.packages_list.go_bottom(),
+            KeyCode::Char('a') => self.change_filters(vec![]),
+            KeyCode::Char('e') => self.add_filter(ListFilter::Explicit),
+            KeyCode::Char('n') => self.add_filter(ListFilter::Dependencies),
+            KeyCode::Char('o') => self.add_filter(ListFilter::Orphans),
+            KeyCode::Char('f') => self.add_filter(ListFilter::Foreign),
+            KeyCode::Char('b') => self.add_filter(ListFilter::Vcs),
+            KeyCode::Char('x') => self.add_filter(ListFilter::Epoch),
+            KeyCode::Backspace => {
+                let last = self.packages_list.filters.len().checked_sub(1);
+                if let Some(index) = last {
+                    self.remove_filter(index);
+                }
+            }
+            KeyCode::Char(c @ '1'..='9') => self.remove_filter(c as usize - '1' as usize),
+            KeyCode::Char('i') => self.config.show_version = !self.config.show_version,
+            KeyCode::Char('O') => self.open_sort_menu(),
+            KeyCode::Char('R') => self.set_sort_order(self.sort_order, !self.sort_descending),
+            KeyCode::Char('s') => self.open_search(),
+            KeyCode::Char(' ') => self.toggle_mark(),
+            KeyCode::Char('v') => self.invert_marks(),
+            KeyCode::Char('V') => self.mark_all_visible(),
+            KeyCode::Char('u') => self.packages_list.marked.clear(),
+            KeyCode::Char('d') => self.confirm_remove(),
+            KeyCode::Char('E') => self.confirm_install_reason(true),
+            KeyCode::Char('D') => self.confirm_install_reason(false),
+            KeyCode::Char('y') => self.copy_names(),
+            KeyCode::Char('w') => self.export_names(),
+            KeyCode::Char('c') => self.start_orphan_cleanup(),
+            KeyCode::Char('t') => self.open_dependency_tree(false),
+            KeyCode::Char('T') => self.open_dependency_tree(true),
+            KeyCode::Char('B') => self.open_downgrade(),
+            KeyCode::Char('l') => self.open_file_list(),
+            KeyCode::Char('L') => {
+                self.config.table_view = !self.config.table_view;
+                self.load_list_data();
+                self.save_list_layout();
+            }
+            KeyCode::Char('z') => self.zoomed = !self.zoomed,
+            KeyCode::Char('<') => self.resize_list(-5),
+            KeyCode::Char('>') => self.resize_list(5),
+            KeyCode::Char('|') => {
+                self.column_menu.select(Some(0));
+                self.current_screen = Screens::ColumnMenu;
+            }
+            _ => {}
+        }
+    }
+
+    fn handle_sync_list_key(&mut self, key: KeyCode) {
+        match key {
+            KeyCode::Up | KeyCode::Char('k') => self.sync_list.previous(),
+            KeyCode::Down | KeyCode::Char('j') => self.sync_list.next(),
+            KeyCode::Char('g') => self.sync_list.go_top(),
+            KeyCode::Char('G') => self.sync_list.go_bottom(),
+            KeyCode::Char('a') => self.change_sync_filters(vec![]),
+            KeyCode::Char('s') => self.open_search(),
+            KeyCode::Char(' ') => {
+                if let Some(package) = self.sync_list.selected() {
+                    let name = format!("{}/{}", package.repo, package.name);
+                    if !self.sync_list.marked.remove(&name) {
+                        self.sync_list.marked.insert(name);
+                    }
+                    self.sync_list.next();
+                }
+            }
+            KeyCode::Char('u') => self.sync_list.marked.clear(),
+            KeyCode::Char('i') => self.confirm_install(),
+            _ => {}
+        }
+    }
+
+    fn handle_updates_key(&mut self, key: KeyCode) {
+        match key {
+            KeyCode::Up | KeyCode::Char('k') => self.updates_list.previous(),
+            KeyCode::Down | KeyCode::Char('j') => self.updates_list.next(),
+            KeyCode::Char('g') => self.updates_list.go_top(),
+            KeyCode::Char('G') => self.updates_list.go_bottom(),
+            KeyCode::Char(' ') => {
+                if let Some(update) = self.updates_list.selected() {
+                    let name = format!("{}/{}", update.repo, update.name);
+                    if !self.updates_list.marked.remove(&name) {
+                        self.updates_list.marked.insert(name);
+                    }
+                    self.updates_list.next();
+                }
+            }
+            KeyCode::Char('u') => self.updates_list.marked.clear(),
+            KeyCode::Char('i') => self.confirm_upgrade_selected(),
+            KeyCode::Char('A') => self.confirm_upgrade_all(),
+            _ => {}
+        }
+    }
+
+    fn handle_history_key(&mut self, key: KeyCode) {
+        if self.show_transactions {
+            match key {
+                KeyCode::Up | KeyCode::Char('k') => return self.history_transactions.previous(),
+                KeyCode::Down | KeyCode::Char('j') => return self.history_transactions.next(),
+                KeyCode::Char('g') => return self.history_transactions.go_top(),
+                KeyCode::Char('G') => return self.history_transactions.go_bottom(),
+                KeyCode::Char('u') => return self.confirm_rollback(),
+                _ => {}
+            }
+        }
+
+        match key {
+            KeyCode::Up | KeyCode::Char('k') => self.history_list.previous(),
+            KeyCode::Down | KeyCode::Char('j') => self.history_list.next(),
+            KeyCode::Char('g') => self.history_list.go_top(),
+            KeyCode::Char('G') => self.history_list.go_bottom(),
+            KeyCode::Char('t') => self.show_transactions = !self.show_transactions,
+            KeyCode::Char('f') => self.change_history_kind(self.history_kind.next()),
+            KeyCode::Char('F') => self.change_history_kind(self.history_kind.previous()),
+            KeyCode::Char('s') => self.open_search(),
+            KeyCode::Char('a') => {
+                self.history_kind = HistoryKind::All;
+                self.change_history_filters(vec![]);
+            }
+            KeyCode::Enter => {
+                let name = self
+                    .history_list
+                    .selected()
+                    .and_then(|e| e.package())
+                    .map(|n| n.to_string());
+                if let Some(name) = name {
+                    if self.select_in_packages(&name) {
+                        self.active_menu_item = MenuItem::PackageList;
+                    } else {
+                        self.message = Some(format!("{} isn't installed", name));
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn handle_cache_key(&mut self, key: KeyCode) {
+        match key {
+            KeyCode::Up | KeyCode::Char('k') => self.cache_list.previous(),
+            KeyCode::Down | KeyCode::Char('j') => self.cache_list.next(),
+            KeyCode::Char('g') => self.cache_list.go_top(),
+            KeyCode::Char('G') => self.cache_list.go_bottom(),
+            KeyCode::Char('+') => self.cache_keep += 1,
+            KeyCode::Char('-') => self.cache_keep = self.cache_keep.saturating_sub(1),
+            KeyCode::Char('x') => self.confirm_prune(PrunePolicy::KeepRecent(self.cache_keep)),
+            KeyCode::Char('X') => self.confirm_prune(PrunePolicy::Uninstalled),
+            _ => {}
+        }
+    }
+
+    fn switch_menu_item(&mut self, item: MenuItem) {
+        self.active_menu_item = item;
+        // The updates are worked out from the sync packages so both need them.
+        if matches!(item, MenuItem::SyncList | MenuItem::Updates)
+            && self.sync_list.items.is_empty()
+            && !self.loading_sync_packages
+        {
+            self.reload_sync_packages();
+        }
+        if item == MenuItem::Cache && self.cache_list.items.is_empty() && !self.loading_cache {
+            self.reload_cache();
+        }
+    }
+
+    fn open_search(&mut self) {
+        self.filters_before_search = Some(match self.active_menu_item {
+            MenuItem::SyncList => self.sync_list.filters.clone(),
+            MenuItem::History => self.history_list.filters.clone(),
+            _ => self.packages_list.filters.clone(),
+        });
+        self.current_screen = Screens::FilterInput;
+    }
+
+    /// Applies what was typed so far in the search, as a new term at the end of the chain.
+    fn search(&mut self) {
+        let mut filters = self.filters_before_search.clone().unwrap_or_default();
+        filters.push(ListFilter::Search(self.filter_input.value().to_string()));
+        self.set_search_filters(filters);
+    }
+
+    fn set_search_filters(&mut self, filters: Vec<ListFilter>) {
+        match self.active_menu_item {
+            MenuItem::PackageList => self.change_filters(filters),
+            MenuItem::SyncList => self.change_sync_filters(filters),
+            MenuItem::History => self.change_history_filters(filters),
+            // Nothing to search on the other tabs.
+            _ => {}
+        }
+    }
+
+    /// Adds `filter` to the chain, or takes it out if it's already there. Explicit,
+    /// dependencies, orphans and foreign can't be combined, so they replace each other.
+    fn add_filter(&mut self, filter: ListFilter) {
+        let mut filters = self.packages_list.filters.clone();
+        if filters.contains(&filter) {
+            filters.retain(|f| *f != filter);
+        } else {
+            if filter.is_package_type() {
+                filters.retain(|f| !f.is_package_type());
+            }
+            filters.push(filter);
+        }
+        self.change_filters(filters);
+    }
+
+    fn remove_filter(&mut self, index: usize) {
+        if index < self.packages_list.filters.len() {
+            let mut filters = self.packages_list.filters.clone();
+            filters.remove(index);
+            self.change_filters(filters);
+        }
+    }
+
+    fn change_filters(&mut self, filters: Vec<ListFilter>) {
+        self.packages_list.filters = filters;
+        self.load_list_data();
+        self.apply_filter();
+        self.packages_list.go_top();
+    }
+
+    /// Only searching is supported for the sync packages, any other term is ignored.
+    fn change_sync_filters(&mut self, filters: Vec<ListFilter>) {
+        self.sync_list.filters = filters;
+        // A search that doesn't parse shows nothing until it's fixed.
+        let queries: Vec<Option<Query>> = self
+            .sync_list
+            .filters
+            .iter()
+            .filter_map(|f| match f {
+                ListFilter::Search(s) => Some(Query::parse(s).ok()),
+                _ => None,
+            })
+            .collect();
+
+        let mut matches: Vec<(i64, SyncPackage)> = self
+            .sync_list
+            .items
+            .iter()
+            .filter_map(|p| {
+                let target = Target {
+                    name: &p.name,
+                    repo: Some(&p.repo),
+                    description: &p.description,
+                    depends: &p.depends_on,
+                    size: Some(p.installed_size),
+                    ..Default::default()
+                };
+                let mut score = 0;
+                for query in &queries {
+                    score += query.as_ref()?.score(&target)?;
+                }
+                Some((score, p.clone()))
+            })
+            .collect();
+        if self.config.rank_search_by_score {
+            // Stable, so equally good matches stay in alphabetical order.
+            matches.sort_by_key(|(score, _)| std::cmp::Reverse(*score));
+        }
+        self.sync_list.filtered_items = matches.into_iter().map(|(_, p)| p).collect();
+        self.sync_list.go_top();
+    }
+
+    fn set_history(&mut self, history: Result<Vec<HistoryEvent>, String>) {
+        self.loading_history = false;
+        match history {
+            Ok(mut events) => {
+                self.last_upgrades = history::last_upgrades(&events);
+                let mut transactions = history::transactions(&events);
+                transactions.reverse();
+                self.history_transactions.items = transactions;
+                events.reverse();
+                self.history_list.items = events;
+                self.history_error = None;
+            }
+            Err(error) => {
+                self.history_list.items.clear();
+                self.history_transactions.items.clear();
+                self.history_error = Some(error);
+            }
+        }
+
+        // Stay on the same event when the log is read again. New events go on top, so the
+        // index only helps if the event is gone.
+        let index = self.history_list.state.selected();
+        let event = self.history_list.selected().cloned();
+        let transaction_index = self.history_transactions.state.selected();
+        let transaction = self.history_transactions.selected().map(|t| t.timestamp);
+        self.change_history_filters(self.history_list.filters.clone());
+        if let Some(index) = index {
+            self.history_list.reselect(index, |e| {
+                event
+                    .as_ref()
+                    .is_some_and(|event| e.timestamp == event.timestamp && e.kind == event.kind)
+            });
+        }
+        if let Some(index) = transaction_index {
+            self.history_transactions
+                .reselect(index, |t| Some(t.timestamp) == transaction);
+        }
+    }
+
+    fn change_history_kind(&mut self, kind: HistoryKind) {
+        self.history_kind = kind;
+        self.change_history_filters(self.history_list.filters.clone());
+    }
+
+    /// Searches match any part of an event's line, e.g. a package name or a hook.
+    fn change_history_filters(&mut self, filters: Vec<ListFilter>) {
+        self.history_list.filters = filters;
+        let searches: Vec<String> = self
+            .history_list
+            .filters
+            .iter()
+            .filter_map(|f| match f {
+                ListFilter::Search(s) => Some(s.to_lowercase()),
+                _ => None,
+            })
+            .collect();
+
+        let matches = |line: String| {
+            let line = line.to_lowercase();
+            searches.iter().all(|s| line.contains(s.as_str()))
+        };
+        let event_line = |e: &HistoryEvent| format!("{} {}", e.kind.action(), e.kind.subject());
+
+        self.history_list.filtered_items = self
+            .history_list
+            .items
+            .iter()
+            .filter(|e| self.history_kind.matches(&e.kind))
+            .filter(|e| matches(event_line(e)))
+            .cloned()
+            .collect();
+        self.history_list.go_top();
+
+        // A transaction matches if its command or any of its changes do.
+        self.history_transactions.filtered_items = self
+            .history_transactions
             .items
-            .clone()
-            .into_iter()
-            .filter(|p| match self.packages_list.list_filter.clone() {
-                ListFilter::All => true,
-                ListFilter::Explicit => p.package_type == PackageType::Explicit,
-                ListFilter::Orphans => p.package_type == PackageType::Orphan,
-                ListFilter::Foreign => p.package_type == PackageType::Foreign,
-                // TODO: Make the search a bit smarter??
-                ListFilter::Search(s) => p.name.contains(s.as_str()),
+            .iter()
+            .filter(|t| {
+                let lines = t.changes.iter().map(event_line);
+                matches(
+                    t.command
+                        .iter()
+                        .cloned()
+                        .chain(lines)
+                        .collect::<Vec<_>>()
+                        .join("\n"),
+                )
             })
+            .cloned()
             .collect();
+        self.history_transactions.go_top();
+    }
 
-        self.go_top();
+    fn reload_cache(&mut self) {
+        self.loading_cache = true;
+        self.worker
+            .send(Job::PackageCache(self.config.cache_dirs.clone()));
     }
 
-    fn go_top(&mut self) {
-        self.packages_list.state.select(Some(0));
+    fn set_cache(&mut self, packages: Vec<CachedPackage>) {
+        self.loading_cache = false;
+        let selected = self.cache_list.state.selected();
+        self.cache_list = StatefulList::with_items(pkgcache::group(packages));
+        if let Some(index) = selected {
+            let last = self.cache_list.items.len().saturating_sub(1);
+            self.cache_list.state.select(Some(index.min(last)));
+        }
+
+        match self.cache_action.take() {
+            Some(CacheAction::Rollback(transaction)) => self.rollback(&transaction),
+            Some(CacheAction::Downgrade { name, installed }) => self.downgrade(name, installed),
+            None => {}
+        }
     }
 
-    fn go_bottom(&mut self) {
+    /// Scans the package cache again, as it may have changed outside pacmanman, and runs
+    /// `action` once that's done.
+    fn with_cache(&mut self, action: CacheAction) {
+        self.message = Some("Scanning the package cache...".to_string());
+        self.cache_action = Some(action);
+        self.reload_cache();
+    }
+
+    /// The installed version of every installed package.
+    pub fn installed_versions(&self) -> HashMap<String, Version> {
         self.packages_list
-            .state
-            .select(Some(self.packages_list.filtered_items.len() - 1));
+            .items
+            .iter()
+            .map(|p| (p.name.clone(), p.version.clone()))
+            .collect()
     }
-}
 
-impl StatefulList {
-    fn new() -> Self {
-        let packages = get_all_packages(&"pacman");
-        StatefulList {
-            state: ListState::default(),
-            items: packages.clone(),
-            last_selected: None,
-            list_filter: ListFilter::All,
-            filtered_items: packages.clone(),
+    /// Shows which cached files `policy` would remove, and removes them once confirmed.
+    fn confirm_prune(&mut self, policy: PrunePolicy) {
+        let removed = pkgcache::prune(&self.cache_list.items, policy, &self.installed_versions());
+        if removed.is_empty() {
+            self.message = Some("Nothing to remove from the package cache".to_string());
+            return;
         }
-    }
 
-    fn next(&mut self) {
-        let i = match self.state.selected() {
-            Some(i) => {
-                if i >= self.filtered_items.len() - 1 {
-                    0
-                } else {
-                    i + 1
-                }
+        // Signatures go along with their package, like paccache does.
+        let mut files = vec![];
+        for package in &removed {
+            files.push(package.path.to_string_lossy().to_string());
+            let signature = format!("{}.sig", package.path.to_string_lossy());
+            if fs::metadata(&signature).is_ok() {
+                files.push(signature);
             }
-            None => self.last_selected.unwrap_or(0),
-        };
-        self.state.select(Some(i));
+        }
+        let mut command = vec!["rm".to_string(), "--".to_string()];
+        command.extend(files.clone());
+
+        let freed: u64 = removed.iter().map(|p| p.size).sum();
+        self.transaction_count += 1;
+        self.transaction = Some(Transaction {
+            id: self.transaction_count,
+            title: format!(
+                "Remove {} cached packages, freeing {}",
+                removed.len(),
+                format_size(freed)
+            ),
+            command: self.privileged(command),
+            notes: vec![],
+            then: None,
+            packages: Some(Ok(removed
+                .iter()
+                .map(|p| format!("{} ({})", p.path.to_string_lossy(), format_size(p.size)))
+                .collect())),
+            targets: files,
+        });
+        self.current_screen = Screens::ConfirmTransaction;
     }
 
-    fn previous(&mut self) {
+    fn reload_sync_packages(&mut self) {
+        self.loading_sync_packages = true;
+        self.worker.send(Job::SyncPackages);
+    }
+
+    fn set_sync_packages(&mut self, packages: Vec<SyncPackage>) {
+        self.loading_sync_packages = false;
+        let installed: HashSet<&str> = self
+            .packages_list
+            .items
+            .iter()
+            .map(|p| p.name.as_str())
+            .collect();
+
+        self.sync_list.items = packages;
+        for package in self.sync_list.items.iter_mut() {
+            package.installed = installed.contains(package.name.as_str());
+        }
+        self.sync_list.marked.clear();
+        self.change_sync_filters(self.sync_list.filters.clone());
+        self.find_updates();
+    }
+
+    /// Compares every installed package against the sync packages. If a package is in more than
+    /// one repo the newest version is used.
+    fn find_updates(&mut self) {
+        let mut newest: HashMap<&str, &SyncPackage> = HashMap::new();
+        for package in &self.sync_list.items {
+            let current = newest.entry(package.name.as_str()).or_insert(package);
+            if version::vercmp(&package.version, &current.version).is_gt() {
+                *current = package;
+            }
+        }
+
+        let mut updates = vec![];
+        let mut missing = vec![];
+        for package in &self.packages_list.items {
+            let Some(sync) = newest.get(package.name.as_str()) else {
+                continue;
+            };
+            // Like `pacman -Qu`, a version without a release isn't newer than one with.
+            if version::vercmp(&sync.version, &package.version).is_le() {
+                continue;
+            }
+
+            let old_installed_size = self
+                .details_cache
+                .get(&package.name)
+                .map(|d| d.installed_bytes);
+            if old_installed_size.is_none() {
+                missing.push(package.name.clone());
+            }
+            updates.push(PackageUpdate {
+                name: package.name.clone(),
+                repo: sync.repo.clone(),
+                old_version: package.version.clone(),
+                new_version: sync.version.clone(),
+                download_size: sync.download_size,
+                old_installed_size,
+                new_installed_size: sync.installed_size,
+            });
+        }
+        // The installed sizes are filled in by `set_details` as they arrive.
+        for name in missing {
+            self.request_details(name);
+        }
+
+        self.updates_list.items = updates.clone();
+        self.updates_list.filtered_items = updates;
+        self.updates_list.marked.clear();
+        self.updates_list.go_top();
+    }
+
+    fn apply_filter(&mut self) {
+        let filters = &self.packages_list.filters;
+        // Parsed once up front, a search that doesn't parse shows nothing until it's fixed.
+        let queries: Vec<Option<Query>> = filters
+            .iter()
+            .map(|f| match f {
+                ListFilter::Search(s) => Query::parse(s).ok(),
+                _ => None,
+            })
+            .collect();
+        let searching = filters.iter().any(|f| matches!(f, ListFilter::Search(_)));
+        // The repo of an installed package is whichever sync repo has it.
+        let repos: HashMap<&str, &str> = self
+            .sync_list
+            .items
+            .iter()
+            .map(|p| (p.name.as_str(), p.repo.as_str()))
+            .collect();
+
+        // Every package that passes all the filters, with how well it matches the searches if
+        // there are any.
+        let mut matches: Vec<(i64, PackageVersionInfo)> = self
+            .packages_list
+            .items
+            .iter()
+            .filter_map(|p| {
+                let details = self.details_cache.get(&p.name);
+                let target = Target {
+                    name: &p.name,
+                    package_type: Some(&p.package_type),
+                    repo: repos.get(p.name.as_str()).copied(),
+                    description: details.map_or("", |d| d.description.as_str()),
+                    depends: details.map_or(&[], |d| d.depends_on.as_slice()),
+                    provides: details.map_or(&[], |d| d.provides.as_slice()),
+                    licenses: details.map_or(&[], |d| d.licenses.as_slice()),
+                    size: details.map(|d| d.installed_bytes),
+                    files: self
+                        .files
+                        .as_ref()
+                        .and_then(|f| f.get(&p.name))
+                        .map_or(&[], |f| f.as_slice()),
+                };
+
+                let mut score = 0;
+                for (filter, query) in filters.iter().zip(&queries) {
+                    score += match filter {
+                        ListFilter::Explicit => {
+                            (p.package_type == PackageType::Explicit).then_some(0)
+                        }
+                        ListFilter::Dependencies => p.package_type.is_dependency().then_some(0),
+                        ListFilter::Orphans => (p.package_type == PackageType::Orphan).then_some(0),
+                        ListFilter::Foreign => {
+                            (p.package_type == PackageType::Foreign).then_some(0)
+                        }
+                        ListFilter::Vcs => p.is_vcs().then_some(0),
+                        ListFilter::Epoch => p.version.has_epoch().then_some(0),
+                        ListFilter::Search(_) => query.as_ref().and_then(|q| q.score(&target)),
+                    }?;
+                }
+                Some((score, p.clone()))
+            })
+            .collect();
+
+        // Packages the order doesn't know about yet, e.g. while their details load or when they
+        // were never upgraded, go last whichever way round it is.
+        let numeric_key = |p: &PackageVersionInfo| -> Option<i64> {
+            let details = self.details_cache.get(&p.name);
+            match self.sort_order {
+                SortOrder::InstalledSize => details.map(|d| d.installed_bytes as i64),
+                SortOrder::InstallDate => details.and_then(|d| d.install_date),
+                SortOrder::BuildDate => details.and_then(|d| d.build_date),
+                SortOrder::LastUpgraded => self.last_upgrades.get(&p.name).copied(),
+                SortOrder::RequiredBy => details.map(|d| d.required_by.len() as i64),
+                SortOrder::Name | SortOrder::Version | SortOrder::Repo => None,
+            }
+        };
+        let descending = self.sort_descending;
+        matches.sort_by(|(_, a), (_, b)| {
+            match self.sort_order {
+                SortOrder::Name => compare_known(Some(&a.name), Some(&b.name), descending),
+                SortOrder::Version => compare_known(Some(&a.version), Some(&b.version), descending),
+                SortOrder::Repo => compare_known(
+                    repos.get(a.name.as_str()),
+                    repos.get(b.name.as_str()),
+                    descending,
+                ),
+                _ => compare_known(numeric_key(a), numeric_key(b), descending),
+            }
+            .then(a.name.cmp(&b.name))
+        });
+        if searching && self.config.rank_search_by_score {
+            // Stable, so equally good matches keep the sort order.
+            matches.sort_by_key(|(score, _)| std::cmp::Reverse(*score));
+        }
+
+        self.packages_list.filtered_items = matches.into_iter().map(|(_, p)| p).collect();
+    }
+
+    /// Starts loading whatever the current searches and sort order need that isn't there yet.
+    /// The list is filtered and sorted again as it arrives, see `read_job_results`.
+    fn load_list_data(&mut self) {
+        let queries = search_queries(&self.packages_list.filters);
+
+        let columns = self.shown_columns();
+        let columns_need_details = columns
+            .iter()
+            .any(|c| matches!(c, Column::Size | Column::InstallDate));
+        if queries.iter().any(Query::needs_details)
+            || self.sort_order.needs_details()
+            || columns_need_details
+        {
+            let names: Vec<String> = self
+                .packages_list
+                .items
+                .iter()
+                .filter(|p| self.details_cache.needs_fetch(&p.name))
+                .map(|p| p.name.clone())
+                .collect();
+            for name in names {
+                self.request_details(name);
+            }
+        }
+        if queries.iter().any(Query::needs_files) {
+            self.load_files();
+        }
+        if (queries.iter().any(Query::needs_repo)
+            || self.sort_order == SortOrder::Repo
+            || columns.contains(&Column::Repo))
+            && self.sync_list.items.is_empty()
+            && !self.loading_sync_packages
+        {
+            self.reload_sync_packages();
+        }
+    }
+
+    /// Filters the packages again, keeping the selected package selected if it still matches.
+    fn refilter(&mut self) {
+        let selected = self.selected_package().map(|p| p.name.clone());
+        self.apply_filter();
+        self.select_package(selected);
+    }
+
+    /// Selects the package called `name`, or the same row as before if it's gone.
+    fn select_package(&mut self, name: Option<String>) {
+        let filtered = &self.packages_list.filtered_items;
+        let index = name
+            .and_then(|name| filtered.iter().position(|p| p.name == name))
+            .or(self.packages_list.state.selected())
+            .or((!filtered.is_empty()).then_some(0))
+            .map(|i| i.min(filtered.len().saturating_sub(1)));
+        self.packages_list.state.select(index);
+    }
+
+    fn open_sort_menu(&mut self) {
+        let index = SortOrder::ALL.iter().position(|o| *o == self.sort_order);
+        self.sort_menu.select(index);
+        self.current_screen = Screens::SortMenu;
+    }
+
+    fn move_sort_menu_selection(&mut self, delta: isize) {
+        let index = self.sort_menu.selected().unwrap_or_default() as isize + delta;
+        let index = index.clamp(0, SortOrder::ALL.len() as isize - 1) as usize;
+        self.sort_menu.select(Some(index));
+    }
+
+    /// Sorts by the highlighted order, picking the order it's already sorted by flips it.
+    fn pick_sort_order(&mut self) {
+        let Some(order) = self.sort_menu.selected().map(|i| SortOrder::ALL[i]) else {
+            return;
+        };
+        let descending = if order == self.sort_order {
+            !self.sort_descending
+        } else {
+            order.descending_by_default()
+        };
+        self.set_sort_order(order, descending);
+        self.current_screen = Screens::DetailsList;
+    }
+
+    fn set_sort_order(&mut self, order: SortOrder, descending: bool) {
+        self.sort_order = order;
+        self.sort_descending = descending;
+        self.load_list_data();
+        self.refilter();
+    }
+
+    /// Reloads the packages from the backend in the background, see `set_packages`.
+    fn reload_packages(&mut self) {
+        self.loading_packages = true;
+        self.worker.send(Job::Packages);
+    }
+
+    /// Replaces the packages, keeping the current filter and selection. Packages are
+    /// reclassified so a changed install reason shows up straight away.
+    fn set_packages(&mut self, mut packages: Vec<PackageVersionInfo>, stamp: Option<u64>) {
+        self.loading_packages = false;
+        let selected = self.selected_package().map(|p| p.name.clone());
+
+        self.details_cache.update(&mut packages, stamp);
+        self.packages_list.items = packages;
+        let list = &mut self.packages_list;
+        list.marked
+            .retain(|name| list.items.iter().any(|p| p.name == *name));
+        self.pending_details.clear();
+        self.dependency_graph = None;
+        self.files = None;
+        // Whatever changed the packages also added to the log.
+        if !self.loading_history {
+            self.loading_history = true;
+            self.worker.send(Job::History);
+        }
+        self.load_list_data();
+        self.apply_filter();
+        self.select_package(selected);
+
+        // A cleanup waits for the packages to be reloaded before offering the next orphans.
+        if self.orphan_cleanup.is_some() && self.current_screen == Screens::DetailsList {
+            self.continue_orphan_cleanup();
+        }
+        if self.dependency_tree.is_some() {
+            self.refresh_tree();
+        }
+    }
+
+    /// The columns of the table view, if it's on.
+    pub fn shown_columns(&self) -> Vec<Column> {
+        if !self.config.table_view {
+            return vec![];
+        }
+        self.config
+            .columns
+            .iter()
+            .filter(|c| c.shown)
+            .map(|c| c.column)
+            .collect()
+    }
+
+    /// Widens the package list of the current view by `delta` percent.
+    fn resize_list(&mut self, delta: i16) {
+        let width = if self.config.table_view {
+            &mut self.config.table_width
+        } else {
+            &mut self.config.list_width
+        };
+        *width = (*width as i16 + delta).clamp(10, 90) as u16;
+        self.zoomed = false;
+        self.save_list_layout();
+    }
+
+    fn move_column_menu_selection(&mut self, delta: isize) {
+        let index = self.column_menu.selected().unwrap_or_default() as isize + delta;
+        let index = index.clamp(0, self.config.columns.len() as isize - 1) as usize;
+        self.column_menu.select(Some(index));
+    }
+
+    fn toggle_column(&mut self) {
+        let Some(index) = self.column_menu.selected() else {
+            return;
+        };
+        let column = &mut self.config.columns[index];
+        column.shown = !column.shown;
+        self.load_list_data();
+        self.save_list_layout();
+    }
+
+    /// Moves the highlighted column `delta` places left or right in the table.
+    fn move_column(&mut self, delta: isize) {
+        let Some(index) = self.column_menu.selected() else {
+            return;
+        };
+        let target = index as isize + delta;
+        if target < 0 || target >= self.config.columns.len() as isize {
+            return;
+        }
+        self.config.columns.swap(index, target as usize);
+        self.column_menu.select(Some(target as usize));
+        self.save_list_layout();
+    }
+
+    fn resize_column(&mut self, delta: i16) {
+        let Some(index) = self.column_menu.selected() else {
+            return;
+        };
+        let column = &mut self.config.columns[index];
+        column.width = (column.width as i16 + delta).clamp(3, 100) as u16;
+        self.save_list_layout();
+    }
+
+    fn save_list_layout(&mut self) {
+        if let Err(e) = self.config.save_list_layout() {
+            self.message = Some(format!("Could not save the layout: {}", e));
+        }
+    }
+
+    /// Asks for the details of the packages around the cursor that haven't been loaded yet, or
+    /// were loaded before the database last changed, since those are shown in full.
+    fn prefetch_details(&mut self) {
+        const AROUND: usize = 10;
+
+        let index = self.packages_list.state.selected().unwrap_or_default();
+        let start = index.saturating_sub(AROUND);
+        let names: Vec<String> = self
+            .packages_list
+            .filtered_items
+            .iter()
+            .skip(start)
+            .take(AROUND * 2 + 1)
+            .filter(|p| self.details_cache.is_outdated(&p.name))
+            .map(|p| p.name.clone())
+            .collect();
+        for name in names {
+            self.request_details(name);
+        }
+    }
+
+    fn request_details(&mut self, name: String) {
+        if self.pending_details.insert(name.clone()) {
+            self.worker.send(Job::Details(name));
+        }
+    }
+
+    fn set_details(&mut self, name: String, details: PackageDetails) {
+        self.pending_details.remove(&name);
+        // Package reloads run alongside, so these may be from before an upgrade.
+        let installed = self.packages_list.items.iter().find(|p| p.name == name);
+        if installed.is_some_and(|p| p.version.to_string() != details.version) {
+            return;
+        }
+
+        let updates = &mut self.updates_list;
+        for update in updates
+            .items
+            .iter_mut()
+            .chain(updates.filtered_items.iter_mut())
+        {
+            if update.name == name {
+                update.old_installed_size = Some(details.installed_bytes);
+            }
+        }
+
+        if let Some(cleanup) = self.orphan_cleanup.as_mut() {
+            for (orphan, size) in cleanup.orphans.iter_mut() {
+                if *orphan == name {
+                    *size = details.installed_bytes;
+                }
+            }
+        }
+
+        self.details_cache.insert(name, details);
+    }
+
+    /// Handles the jobs the worker finished since the last frame.
+    fn read_job_results(&mut self) {
+        let results: Vec<JobResult> = self.worker.results().collect();
+        // Whether a search may match or the sort order sort differently now, once per frame is
+        // plenty.
+        let mut list_data = false;
+        for result in results {
+            match result {
+                JobResult::Packages(packages, stamp) => self.set_packages(packages, stamp),
+                JobResult::SyncPackages(packages) => {
+                    self.set_sync_packages(packages);
+                    list_data = true;
+                }
+                JobResult::Details(name, details) => {
+                    self.set_details(name, *details);
+                    list_data = true;
+                }
+                JobResult::Files(files) => {
+                    self.loading_files = false;
+                    self.files = Some(files);
+                    self.refresh_file_list();
+                    self.find_owner();
+                    list_data = true;
+                }
+                JobResult::FileTree(package, rows) => self.set_file_tree(package, rows),
+                JobResult::History(history) => {
+                    self.set_history(history);
+                    list_data = true;
+                }
+                JobResult::PackageCache(packages) => self.set_cache(packages),
+                JobResult::Graph(graph) => {
+                    self.loading_graph = false;
+                    self.dependency_graph = Some(graph);
+                    self.refresh_tree();
+                }
+                JobResult::Preview(id, packages) => {
+                    if let Some(transaction) = self.transaction.as_mut() {
+                        if transaction.id == id {
+                            transaction.packages = Some(packages);
+                        }
+                    }
+                }
+            }
+        }
+        let filters = &self.packages_list.filters;
+        let searching = filters.iter().any(|f| matches!(f, ListFilter::Search(_)));
+        let sorting = !matches!(self.sort_order, SortOrder::Name | SortOrder::Version);
+        if list_data && (searching || sorting) {
+            self.refilter();
+        }
+    }
+
+    fn toggle_mark(&mut self) {
+        if let Some(package) = self.selected_package() {
+            let name = package.name.clone();
+            if !self.packages_list.marked.remove(&name) {
+                self.packages_list.marked.insert(name);
+            }
+            self.packages_list.next();
+        }
+    }
+
+    /// Inverts the marks of the packages matching the current filter.
+    fn invert_marks(&mut self) {
+        for package in &self.packages_list.filtered_items {
+            if !self.packages_list.marked.remove(&package.name) {
+                self.packages_list.marked.insert(package.name.clone());
+            }
+        }
+    }
+
+    fn mark_all_visible(&mut self) {
+        for package in &self.packages_list.filtered_items {
+            self.packages_list.marked.insert(package.name.clone());
+        }
+    }
+
+    /// The marked packages, or the selected one if nothing is marked.
+    fn target_packages(&self) -> Vec<String> {
+        if self.packages_list.marked.is_empty() {
+            return self
+                .selected_package()
+                .map(|p| vec![p.name.clone()])
+                .unwrap_or_default();
+        }
+
+        let mut names: Vec<String> = self.packages_list.marked.iter().cloned().collect();
+        names.sort();
+        names
+    }
+
+    fn selected_package(&self) -> Option<&PackageVersionInfo> {
+        self.packages_list.selected()
+    }
+
+    fn confirm_remove(&mut self) {
+        let names = self.target_packages();
+        if names.is_empty() {
+            return;
+        }
+
+        let command = self.privileged(self.backend.remove_command(&names));
+        self.confirm_transaction(
+            "Remove packages",
+            command,
+            names.clone(),
+            Preview::Remove(names),
+        );
+    }
+
+    /// Installs the marked sync packages, or the selected one if nothing is marked.
+    fn confirm_install(&mut self) {
+        let mut names: Vec<String> = self.sync_list.marked.iter().cloned().collect();
+        if names.is_empty() {
+            if let Some(package) = self.sync_list.selected() {
+                names.push(format!("{}/{}", package.repo, package.name));
+            }
+        }
+        if names.is_empty() {
+            return;
+        }
+        names.sort();
+
+        let command = self.privileged(self.backend.install_command(&names));
+        self.confirm_transaction(
+            "Install packages",
+            command,
+            names.clone(),
+            Preview::Install(names),
+        );
+    }
+
+    /// Upgrades the marked packages, or the selected one if nothing is marked.
+    fn confirm_upgrade_selected(&mut self) {
+        let mut names: Vec<String> = self.updates_list.marked.iter().cloned().collect();
+        if names.is_empty() {
+            if let Some(update) = self.updates_list.selected() {
+                names.push(format!("{}/{}", update.repo, update.name));
+            }
+        }
+        if names.is_empty() {
+            return;
+        }
+        names.sort();
+
+        let command = self.privileged(self.backend.install_command(&names));
+        self.confirm_transaction(
+            "Upgrade packages",
+            command,
+            names.clone(),
+            Preview::Install(names),
+        );
+    }
+
+    fn confirm_upgrade_all(&mut self) {
+        let command = self.privileged(self.backend.upgrade_command());
+        self.confirm_transaction("Upgrade everything", command, vec![], Preview::Upgrade);
+        if let Some(transaction) = self.transaction.as_mut() {
+            transaction.notes.push(
+                "The packages below are from the databases as they are now. They're synced \
+                 first, so more may be upgraded."
+                    .to_string(),
+            );
+        }
+    }
+
+    /// Marks the target packages as explicitly installed, or as dependencies.
+    fn confirm_install_reason(&mut self, explicit: bool) {
+        let names = self.target_packages();
+        if names.is_empty() {
+            return;
+        }
+
+        self.transaction_count += 1;
+        self.transaction = Some(Transaction {
+            id: self.transaction_count,
+            title: if explicit {
+                "Mark as explicitly installed".to_string()
+            } else {
+                "Mark as dependencies".to_string()
+            },
+            command: self.privileged(self.backend.install_reason_command(&names, explicit)),
+            notes: vec![],
+            then: None,
+            packages: Some(Ok(names.clone())),
+            targets: names,
+        });
+        self.current_screen = Screens::ConfirmTransaction;
+    }
+
+    fn copy_names(&mut self) {
+        let names = self.target_packages();
+        self.message = Some(match copy_to_clipboard(&names.join("\n")) {
+            Ok(_) => format!("Copied {} package names", names.len()),
+            Err(e) => format!("Failed to copy package names: {}", e),
+        });
+    }
+
+    /// Writes the target package names to a new file in `$XDG_DATA_HOME/pacmanman`, one per
+    /// line so it can be fed back into `pacman -S -`.
+    fn export_names(&mut self) {
+        let names = self.target_packages();
+        let Some(dir) = config::data_dir() else {
+            self.message = Some("Failed to export package names: no home directory".to_string());
+            return;
+        };
+        let file_name = Local::now()
+            .format("packages-%Y-%m-%d-%H%M%S.txt")
+            .to_string();
+        let path = dir.join(file_name);
+        let written =
+            fs::create_dir_all(&dir).and_then(|_| fs::write(&path, names.join("\n") + "\n"));
+        self.message = Some(match written {
+            Ok(_) => format!(
+                "Exported {} package names to {}",
+                names.len(),
+                path.display()
+            ),
+            Err(e) => format!("Failed to export package names: {}", e),
+        });
+    }
+
+    fn start_orphan_cleanup(&mut self) {
+        self.orphan_cleanup = Some(OrphanCleanup {
+            orphans: vec![],
+            keep: HashSet::new(),
+            state: ListState::default(),
+        });
+        self.find_orphans();
+        // Show the popup even if there is nothing to clean so it's clear the key did something.
+        self.current_screen = Screens::OrphanCleanup;
+    }
+
+    /// Offers the orphans left after a cleanup, or ends it if there are none.
+    fn continue_orphan_cleanup(&mut self) {
+        // Carried on by `set_packages` once the packages are reloaded.
+        if self.loading_packages {
+            return;
+        }
+        self.find_orphans();
+        match &self.orphan_cleanup {
+            Some(cleanup) if !cleanup.orphans.is_empty() => {
+                self.current_screen = Screens::OrphanCleanup
+            }
+            _ => self.orphan_cleanup = None,
+        }
+    }
+
+    /// Fills the cleanup with every current orphan the user hasn't chosen to keep.
+    fn find_orphans(&mut self) {
+        let Some(cleanup) = self.orphan_cleanup.as_mut() else {
+            return;
+        };
+
+        cleanup.orphans = self
+            .packages_list
+            .items
+            .iter()
+            .filter(|p| p.package_type == PackageType::Orphan && !cleanup.keep.contains(&p.name))
+            .map(|p| {
+                let size = self.details_cache.get(&p.name).map(|d| d.installed_bytes);
+                (p.name.clone(), size.unwrap_or_default())
+            })
+            .collect();
+        cleanup.state.select(Some(0));
+
+        // The sizes are filled in by `set_details` as they arrive.
+        let missing: Vec<String> = self
+            .packages_list
+            .items
+            .iter()
+            .filter(|p| {
+                p.package_type == PackageType::Orphan && self.details_cache.needs_fetch(&p.name)
+            })
+            .map(|p| p.name.clone())
+            .collect();
+        for name in missing {
+            self.request_details(name);
+        }
+    }
+
+    fn move_orphan_selection(&mut self, step: isize) {
+        if let Some(cleanup) = self.orphan_cleanup.as_mut() {
+            let len = cleanup.orphans.len() as isize;
+            if len == 0 {
+                return;
+            }
+            let i = cleanup.state.selected().unwrap_or_default() as isize;
+            cleanup
+                .state
+                .select(Some((i + step).rem_euclid(len) as usize));
+        }
+    }
+
+    fn toggle_orphan(&mut self) {
+        if let Some(cleanup) = self.orphan_cleanup.as_mut() {
+            let Some((name, _)) = cleanup
+                .state
+                .selected()
+                .and_then(|i| cleanup.orphans.get(i))
+            else {
+                return;
+            };
+            if !cleanup.keep.remove(name) {
+                cleanup.keep.insert(name.clone());
+            }
+        }
+    }
+
+    fn confirm_orphan_cleanup(&mut self) {
+        let Some(cleanup) = &self.orphan_cleanup else {
+            return;
+        };
+
+        let names: Vec<String> = cleanup
+            .orphans
+            .iter()
+            .filter(|(name, _)| !cleanup.keep.contains(name))
+            .map(|(name, _)| name.clone())
+            .collect();
+        if names.is_empty() {
+            return;
+        }
+
+        let command = self.privileged(self.backend.remove_command(&names));
+        self.confirm_transaction(
+            "Clean orphans",
+            command,
+            names.clone(),
+            Preview::Remove(names),
+        );
+    }
+
+    /// Offers to put every package the selected transaction changed back to the version it had
+    /// before, using the files in the package cache.
+    fn confirm_rollback(&mut self) {
+        if let Some(transaction) = self.history_transactions.selected() {
+            self.with_cache(CacheAction::Rollback(transaction.clone()));
+        }
+    }
+
+    fn rollback(&mut self, transaction: &PastTransaction) {
+        self.message = None;
+        let cache: Vec<CachedPackage> = self
+            .cache_list
+            .items
+            .iter()
+            .flat_map(|g| g.packages.iter().cloned())
+            .collect();
+        let rollback = transaction.rollback(&cache);
+        if rollback.files.is_empty() && rollback.missing.is_empty() {
+            self.message = Some(
+                "Nothing to roll back, the transaction only installed or reinstalled packages"
+                    .to_string(),
+            );
+            return;
+        }
+
+        let mut notes = vec![];
+        if !rollback.installed.is_empty() {
+            notes.push(format!(
+                "Packages it installed are kept, remove them from the package list if needed: {}",
+                rollback.installed.join(" ")
+            ));
+        }
+        if !rollback.removed.is_empty() {
+            notes.push(format!(
+                "Packages it removed come back as explicitly installed, mark the ones that were \
+                 dependencies with D: {}",
+                rollback.removed.join(" ")
+            ));
+        }
+        let files: Vec<String> = rollback
+            .files
+            .iter()
+            .map(|f| f.path.to_string_lossy().to_string())
+            .collect();
+        let lines = rollback
+            .files
+            .iter()
+            .map(|f| format!("{} ({})", f.path.to_string_lossy(), format_size(f.size)))
+            .collect();
+
+        self.transaction_count += 1;
+        self.transaction = Some(Transaction {
+            id: self.transaction_count,
+            title: format!(
+                "Roll back the transaction of {}",
+                format_date(transaction.timestamp)
+            ),
+            command: self.privileged(self.backend.install_files_command(&files)),
+            notes,
+            then: None,
+            packages: Some(if rollback.missing.is_empty() {
+                Ok(lines)
+            } else {
+                Err(format!(
+                    "Not in the package cache: {}",
+                    rollback.missing.join(", ")
+                ))
+            }),
+            targets: files,
+        });
+        self.current_screen = Screens::ConfirmTransaction;
+    }
+
+    fn load_files(&mut self) {
+        if self.files.is_none() && !self.loading_files {
+            self.loading_files = true;
+            self.worker.send(Job::Files);
+        }
+    }
+
+    fn open_file_list(&mut self) {
+        let Some(package) = self.selected_package() else {
+            return;
+        };
+        self.file_list = Some(FileList {
+            package: package.name.clone(),
+            rows: vec![],
+            loading: true,
+            state: ListState::default().with_selected(Some(0)),
+        });
+        self.load_files();
+        self.refresh_file_list();
+        self.current_screen = Screens::Files;
+    }
+
+    /// Asks for the rows of the files popup, once the files are loaded. Reading the size of
+    /// every file can take a while for big packages, so it's done on the worker.
+    fn refresh_file_list(&mut self) {
+        let (Some(files), Some(list)) = (&self.files, self.file_list.as_mut()) else {
+            return;
+        };
+        let backup_files = self
+            .details_cache
+            .get(&list.package)
+            .map(|d| d.backup_files.clone())
+            .unwrap_or_default();
+        let paths = files.get(&list.package).cloned().unwrap_or_default();
+        list.loading = true;
+        self.worker
+            .send(Job::FileTree(list.package.clone(), paths, backup_files));
+    }
+
+    fn set_file_tree(&mut self, package: String, rows: Vec<FileRow>) {
+        // The popup may have been closed, or opened on another package, in the meantime.
+        if let Some(list) = self.file_list.as_mut().filter(|l| l.package == package) {
+            list.rows = rows;
+            list.loading = false;
+        }
+    }
+
+    fn move_file_selection(&mut self, step: isize) {
+        if let Some(list) = self.file_list.as_mut() {
+            let last = list.rows.len().saturating_sub(1) as isize;
+            let i = list.state.selected().unwrap_or_default() as isize;
+            list.state
+                .select(Some(i.saturating_add(step).clamp(0, last) as usize));
+        }
+    }
+
+    /// Goes to the package owning the path typed in the prompt, like `pacman -Qo`. Waits for
+    /// the files if they aren't loaded yet.
+    fn find_owner(&mut self) {
+        if self.pending_owner.is_none() {
+            return;
+        }
+        let Some(files) = &self.files else {
+            self.message = Some("Loading the files of every package...".to_string());
+            self.load_files();
+            return;
+        };
+        let Some(input) = self.pending_owner.take() else {
+            return;
+        };
+
+        let path = files::resolve_path(&input);
+        let owners = files::owners(files, &path);
+        let Some(owner) = owners.first().cloned() else {
+            self.message = Some(format!("No package owns {}", path.display()));
+            return;
+        };
+        self.message = Some(format!(
+            "{} is owned by {}",
+            path.display(),
+            owners.join(" ")
+        ));
+        self.select_in_packages(&owner);
+        self.active_menu_item = MenuItem::PackageList;
+    }
+
+    /// Lists the cached versions of the selected package to go back to.
+    fn open_downgrade(&mut self) {
+        if let Some(package) = self.selected_package() {
+            let action = CacheAction::Downgrade {
+                name: package.name.clone(),
+                installed: package.version.clone(),
+            };
+            self.with_cache(action);
+        }
+    }
+
+    fn downgrade(&mut self, name: String, installed: Version) {
+        self.message = None;
+        let mut packages: Vec<CachedPackage> = self
+            .cache_list
+            .items
+            .iter()
+            .find(|g| g.name == name)
+            .map(|g| g.packages.clone())
+            .unwrap_or_default();
+        if packages.is_empty() {
+            self.message = Some(format!("{} isn't in the package cache", name));
+            return;
+        }
+        packages.reverse();
+
+        // Start on the newest version older than the installed one.
+        let selected = packages
+            .iter()
+            .position(|p| p.version < installed)
+            .unwrap_or_default();
+        self.downgrade = Some(Downgrade {
+            name,
+            installed,
+            packages,
+            ignore: false,
+            state: ListState::default().with_selected(Some(selected)),
+        });
+        self.current_screen = Screens::Downgrade;
+    }
+
+    fn move_downgrade_selection(&mut self, step: isize) {
+        if let Some(downgrade) = self.downgrade.as_mut() {
+            let len = downgrade.packages.len() as isize;
+            let i = downgrade.state.selected().unwrap_or_default() as isize;
+            downgrade
+                .state
+                .select(Some((i + step).clamp(0, len - 1) as usize));
+        }
+    }
+
+    fn confirm_downgrade(&mut self) {
+        let Some(downgrade) = self.downgrade.take() else {
+            return;
+        };
+        let Some(package) = downgrade
+            .state
+            .selected()
+            .and_then(|i| downgrade.packages.get(i))
+        else {
+            return;
+        };
+
+        let file = package.path.to_string_lossy().to_string();
+        let mut notes = vec![];
+        let mut then = None;
+        if downgrade.ignore && self.backend.is_ignored(&downgrade.name) {
+            notes.push(format!("{} is already in IgnorePkg", downgrade.name));
+        } else if downgrade.ignore {
+            notes.push(format!(
+                "Then adds `IgnorePkg = {}` under [options] in {}",
+                downgrade.name,
+                self.config.conf_path.display()
+            ));
+            then = Some(self.privileged(self.backend.ignore_command(&downgrade.name)));
+        }
+
+        self.transaction_count += 1;
+        self.transaction = Some(Transaction {
+            id: self.transaction_count,
+            title: format!(
+                "Install {} {} (installed: {})",
+                downgrade.name, package.version, downgrade.installed
+            ),
+            command: self.privileged(
+                self.backend
+                    .install_files_command(std::slice::from_ref(&file)),
+            ),
+            notes,
+            then,
+            packages: Some(Ok(vec![format!(
+                "{} ({})",
+                file,
+                format_size(package.size)
+            )])),
+            targets: vec![file],
+        });
+        self.current_screen = Screens::ConfirmTransaction;
+    }
+
+    /// Asks the user to confirm `command`, working out what it would touch in the background.
+    fn confirm_transaction(
+        &mut self,
+        title: &str,
+        command: Vec<String>,
+        targets: Vec<String>,
+        preview: Preview,
+    ) {
+        self.transaction_count += 1;
+        self.worker
+            .send(Job::Preview(self.transaction_count, preview));
+        self.transaction = Some(Transaction {
+            id: self.transaction_count,
+            title: title.to_string(),
+            command,
+            notes: vec![],
+            then: None,
+            targets,
+            packages: None,
+        });
+        self.current_screen = Screens::ConfirmTransaction;
+    }
+
+    /// The dependency graph, or `None` while it's being built in the background.
+    pub fn dependency_graph(&mut self) -> Option<&DependencyGraph> {
+        if self.dependency_graph.is_none() && !self.loading_graph && !self.loading_packages {
+            self.loading_graph = true;
+            // Hand over the cached details so only the missing ones are fetched.
+            let packages = self
+                .packages_list
+                .items
+                .iter()
+                .map(|p| {
+                    let mut package = p.clone();
+                    if let Some(details) = self.details_cache.get(&p.name) {
+                        package.set_details(details.clone());
+                    }
+                    package
+                })
+                .collect();
+            self.worker.send(Job::Graph(packages));
+        }
+        self.dependency_graph.as_ref()
+    }
+
+    fn open_dependency_tree(&mut self, reverse: bool) {
+        let Some(package) = self.selected_package() else {
+            return;
+        };
+        let root = package.name.clone();
+
+        self.dependency_tree = Some(DependencyTree {
+            expanded: HashSet::from([vec![root.clone()]]),
+            root,
+            reverse,
+            rows: vec![],
+            state: ListState::default().with_selected(Some(0)),
+        });
+        self.refresh_tree();
+        self.current_screen = Screens::DependencyTree;
+    }
+
+    /// Flattens the tree again after rows were expanded or collapsed.
+    fn refresh_tree(&mut self) {
+        self.dependency_graph();
+        let (Some(graph), Some(tree)) = (&self.dependency_graph, self.dependency_tree.as_mut())
+        else {
+            return;
+        };
+        tree.rows = graph.tree(&tree.root, tree.reverse, &tree.expanded);
+    }
+
+    fn move_tree_selection(&mut self, step: isize) {
+        if let Some(tree) = self.dependency_tree.as_mut() {
+            // The rows are empty until the graph is built.
+            let last = tree.rows.len().saturating_sub(1) as isize;
+            let i = tree.state.selected().unwrap_or_default() as isize;
+            tree.state.select(Some((i + step).clamp(0, last) as usize));
+        }
+    }
+
+    /// Expands or collapses the selected row. Collapsing a row that isn't expanded collapses
+    /// its parent instead.
+    fn expand_tree_row(&mut self, expand: bool) {
+        let Some(tree) = self.dependency_tree.as_mut() else {
+            return;
+        };
+        let Some(row) = tree.state.selected().and_then(|i| tree.rows.get(i)) else {
+            return;
+        };
+
+        let mut path = row.path.clone();
+        if expand {
+            if row.has_children {
+                tree.expanded.insert(path);
+            }
+        } else if !tree.expanded.remove(&path) && path.len() > 1 {
+            path.pop();
+            tree.expanded.remove(&path);
+            let parent = tree.rows.iter().position(|r| r.path == path);
+            tree.state.select(parent);
+        }
+        self.refresh_tree();
+    }
+
+    fn reverse_tree(&mut self) {
+        if let Some(tree) = self.dependency_tree.as_mut() {
+            tree.reverse = !tree.reverse;
+            tree.expanded = HashSet::from([vec![tree.root.clone()]]);
+            tree.state.select(Some(0));
+        }
+        self.refresh_tree();
+    }
+
+    /// Closes the tree and selects the package of the selected row in the list.
+    fn jump_to_tree_row(&mut self) {
+        let Some(tree) = &self.dependency_tree else {
+            return;
+        };
+        let Some(row) = tree.state.selected().and_then(|i| tree.rows.get(i)) else {
+            return;
+        };
+        if !row.link.installed {
+            self.message = Some(format!("{} isn't installed", row.name()));
+            return;
+        }
+        let name = row.name().to_string();
+        self.select_in_packages(&name);
+
+        self.dependency_tree = None;
+        self.current_screen = Screens::DetailsList;
+    }
+
+    /// Selects the installed package `name` in the package list, clearing the filters if they
+    /// hide it. `false` if it isn't installed.
+    fn select_in_packages(&mut self, name: &str) -> bool {
+        let position = |list: &StatefulList<PackageVersionInfo>| {
+            list.filtered_items.iter().position(|p| p.name == name)
+        };
+        if !self.packages_list.items.iter().any(|p| p.name == name) {
+            return false;
+        }
+        if position(&self.packages_list).is_none() {
+            self.change_filters(vec![]);
+        }
+        self.packages_list
+            .state
+            .select(position(&self.packages_list));
+        true
+    }
+
+    /// Prefixes `command` with the privilege command. Commands run in the background where
+    /// there's no terminal to ask for a password, so `-n` makes them fail right away instead
+    /// if the password isn't remembered any more, see `runner::is_authenticated`.
+    fn privileged(&self, command: Vec<String>) -> Vec<String> {
+        if self.config.privilege_command.is_empty() {
+            return command;
+        }
+
+        let mut privileged = vec![self.config.privilege_command.clone(), "-n".to_string()];
+        privileged.extend(command);
+        privileged
+    }
+
+    /// Runs the confirmed transaction in the background, streaming its output into the log.
+    fn run_transaction(&mut self, terminal: &mut Terminal<impl Backend>) -> io::Result<()> {
+        // Wait for the preview so it's clear what is about to happen.
+        if self
+            .transaction
+            .as_ref()
+            .is_some_and(|t| t.packages.is_none())
+        {
+            return Ok(());
+        }
+        let Some(transaction) = self.transaction.take() else {
+            return Ok(());
+        };
+        self.current_screen = Screens::CommandLog;
+        self.command_log = vec![format!("$ {}", transaction.command.join(" "))];
+
+        if !matches!(transaction.packages, Some(Ok(_))) {
+            self.command_log.push("Nothing was run.".to_string());
+            return Ok(());
+        }
+
+        let privilege_command = &self.config.privilege_command;
+        if !privilege_command.is_empty() && !runner::is_authenticated(privilege_command) {
+            let authenticated = runner::authenticate(privilege_command, &transaction.command)?;
+            terminal.clear()?;
+            if !authenticated {
+                self.command_log.push("Authentication failed.".to_string());
+                return Ok(());
+            }
+            // The command runs without a terminal, so it can't ask for the password again.
+            if !runner::is_authenticated(privilege_command) {
+                self.command_log.push(format!(
+                    "{} doesn't remember the password, so the command can't run in the \
+                     background. For doas, add `persist` to the rule in doas.conf.",
+                    privilege_command
+                ));
+                return Ok(());
+            }
+        }
+
+        self.next_command = transaction.then;
+        match runner::spawn(&transaction.command) {
+            Ok(output) => self.command_output = Some(output),
+            Err(e) => self
+                .command_log
+                .push(format!("Failed to run command: {}", e)),
+        }
+        Ok(())
+    }
+
+    fn read_command_output(&mut self) {
+        let Some(output) = &self.command_output else {
+            return;
+        };
+
+        let mut finished = None;
+        for line in output.try_iter() {
+            match line {
+                CommandOutput::Line(l) => self.command_log.push(l),
+                CommandOutput::Finished(success) => {
+                    self.command_log.push(if success {
+                        "Done.".to_string()
+                    } else {
+                        "The command failed.".to_string()
+                    });
+                    finished = Some(success);
+                }
+            }
+        }
+
+        if let Some(success) = finished {
+            self.command_output = None;
+            let next_command = self.next_command.take();
+            let privilege_command = &self.config.privilege_command;
+            if let Some(command) = next_command.filter(|_| success) {
+                self.command_log.push(format!("$ {}", command.join(" ")));
+                // The password may have been forgotten while the first command ran.
+                if !privilege_command.is_empty() && !runner::is_authenticated(privilege_command) {
+                    self.command_log.push(format!(
+                        "{} doesn't remember the password any more, so this wasn't run. Run \
+                         it yourself if it's still needed.",
+                        privilege_command
+                    ));
+                } else {
+                    match runner::spawn(&command) {
+                        Ok(output) => {
+                            self.command_output = Some(output);
+                            return;
+                        }
+                        Err(e) => self
+                            .command_log
+                            .push(format!("Failed to run command: {}", e)),
+                    }
+                }
+            }
+
+            self.packages_list.marked.clear();
+            self.refresh();
+        }
+    }
+
+    /// Reloads the installed packages, and the sync packages and package cache if they were
+    /// loaded.
+    fn refresh(&mut self) {
+        self.reload_packages();
+        if !self.sync_list.items.is_empty() || self.loading_sync_packages {
+            self.reload_sync_packages();
+        }
+        if !self.cache_list.items.is_empty() || self.loading_cache {
+            self.reload_cache();
+        }
+    }
+
+    /// Reloads everything once the database settles after being changed outside pacmanman.
+    fn check_database_changes(&mut self) {
+        const SETTLE: Duration = Duration::from_millis(500);
+
+        if self.watcher.as_ref().is_some_and(|w| w.changed()) {
+            self.database_changed = Some(Instant::now());
+        }
+        let Some(changed) = self.database_changed else {
+            return;
+        };
+        // Wait for a running transaction, and for the last reload to see what it picked up.
+        if changed.elapsed() < SETTLE || self.backend.database_locked() || self.loading_packages {
+            return;
+        }
+        self.database_changed = None;
+
+        // Our own transactions already reload once they finish, see `read_command_output`.
+        let stamp = self.backend.database_stamp();
+        if self.command_output.is_none() && (stamp.is_none() || stamp != self.details_cache.stamp())
+        {
+            self.refresh();
+            self.message = Some("The package database changed, reloading...".to_string());
+        }
+    }
+}
+
+impl<T: Clone> StatefulList<T> {
+    fn with_items(items: Vec<T>) -> Self {
+        StatefulList {
+            // Start on the first item so actions have something to apply to straight away.
+            state: ListState::default().with_selected((!items.is_empty()).then_some(0)),
+            items: items.clone(),
+            last_selected: None,
+            filters: vec![],
+            filtered_items: items,
+            marked: HashSet::new(),
+        }
+    }
+
+    pub fn selected(&self) -> Option<&T> {
+        self.state
+            .selected()
+            .and_then(|i| self.filtered_items.get(i))
+    }
+
+    fn go_top(&mut self) {
+        self.state.select(Some(0));
+    }
+
+    /// Selects the first item matching `is_selected` after the items changed, or the row at
+    /// `index` if there's none.
+    fn reselect(&mut self, index: usize, is_selected: impl Fn(&T) -> bool) {
+        let last = self.filtered_items.len().saturating_sub(1);
+        let index = self
+            .filtered_items
+            .iter()
+            .position(is_selected)
+            .unwrap_or(index.min(last));
+        self.state.select(Some(index));
+    }
+
+    fn go_bottom(&mut self) {
+        self.state
+            .select(Some(self.filtered_items.len().saturating_sub(1)));
+    }
+
+    fn next(&mut self) {
+        if self.filtered_items.is_empty() {
+            return;
+        }
+
+        let i = match self.state.selected() {
+            Some(i) => {
+                if i >= self.filtered_items.len() - 1 {
+                    0
+                } else {
+                    i + 1
+                }
+            }
+            None => self.last_selected.unwrap_or(0),
+        };
+        self.state.select(Some(i));
+    }
+
+    fn previous(&mut self) {
+        if self.filtered_items.is_empty() {
+            return;
+        }
         let i = match self.state.selected() {
             Some(i) => {
                 if i == 0 {
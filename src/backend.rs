@@ -0,0 +1,248 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::commands::{self, PackageDetails, PackageVersionInfo, SyncPackage};
+use crate::config::Config;
+use crate::database;
+use crate::history::{self, HistoryEvent};
+
+/// A package manager pacmanman can list and manage packages with.
+///
+/// `app.rs` and `ui.rs` only talk to the package manager through this trait, so adding another
+/// one means implementing it and registering it in `from_name`. Everything is called from a
+/// background thread, see `worker.rs`, except the `*_command` methods which must not block.
+pub trait PackageBackend: Send + Sync {
+    /// Name used to select the backend from the config file or the command line.
+    fn name(&self) -> &str;
+
+    /// Every package to show in the list, classified as explicit, orphan or foreign.
+    fn list_packages(&self) -> Vec<PackageVersionInfo>;
+
+    fn get_details(&self, package_name: &str) -> PackageDetails;
+
+    /// The files of every installed package, keyed by package name.
+    fn list_files(&self) -> HashMap<String, Vec<String>>;
+
+    /// Everything the package manager logged, oldest first. Errors if the log can't be read.
+    fn history(&self) -> Result<Vec<HistoryEvent>, String>;
+
+    /// Changes whenever the installed packages do, e.g. the database's modification time.
+    /// `None` if it can't be told, then cached details are always fetched again.
+    fn database_stamp(&self) -> Option<u64>;
+
+    /// Directories that change whenever packages are installed or removed. They're watched to
+    /// reload the packages after changes made outside pacmanman.
+    fn database_paths(&self) -> Vec<PathBuf>;
+
+    /// Whether a transaction is running, reloading waits until it's done.
+    fn database_locked(&self) -> bool;
+
+    /// Every package available to install.
+    fn list_sync_packages(&self) -> Vec<SyncPackage>;
+
+    /// Command line that installs the given packages without asking for confirmation.
+    /// Names can be prefixed with the repo, e.g. `extra/firefox`.
+    fn install_command(&self, package_names: &[String]) -> Vec<String>;
+
+    /// Every package installing `package_names` would pull in, as `repo/name version`.
+    /// Errors with the package manager's message if the install isn't possible.
+    fn install_preview(&self, package_names: &[String]) -> Result<Vec<String>, String>;
+
+    /// Command line that installs the given package files, e.g. from the package cache, without
+    /// asking for confirmation.
+    fn install_files_command(&self, paths: &[String]) -> Vec<String>;
+
+    /// Command line that keeps `package_name` from being upgraded, e.g. after downgrading it.
+    /// Check `is_ignored` first, running it twice may add the package twice.
+    fn ignore_command(&self, package_name: &str) -> Vec<String>;
+
+    /// Whether `package_name` is already kept from being upgraded.
+    fn is_ignored(&self, package_name: &str) -> bool;
+
+    /// Command line that removes the given packages without asking for confirmation.
+    fn remove_command(&self, package_names: &[String]) -> Vec<String>;
+
+    /// Command line that syncs the package databases and upgrades everything.
+    fn upgrade_command(&self) -> Vec<String>;
+
+    /// Every package a full upgrade would touch according to the current package databases,
+    /// as `repo/name version`.
+    fn upgrade_preview(&self) -> Result<Vec<String>, String>;
+
+    /// Command line that marks the given packages as explicitly installed, or as dependencies.
+    fn install_reason_command(&self, package_names: &[String], explicit: bool) -> Vec<String>;
+
+    /// Every package removing `package_names` would take with it, as `name version`.
+    /// Errors with the package manager's message if the removal isn't possible.
+    fn remove_preview(&self, package_names: &[String]) -> Result<Vec<String>, String>;
+}
+
+/// Builds the backend called `name`, or `None` if there isn't one.
+pub fn from_name(name: &str, config: &Config) -> Option<Box<dyn PackageBackend>> {
+    match name {
+        "pacman" => Some(Box::new(PacmanBackend {
+            db_path: config.db_path.clone(),
+            log_path: config.log_path.clone(),
+            conf_path: config.conf_path.clone(),
+            remove_flags: config.remove_flags.clone(),
+        })),
+        _ => None,
+    }
+}
+
+pub struct PacmanBackend {
+    db_path: PathBuf,
+    log_path: PathBuf,
+    conf_path: PathBuf,
+    // e.g. `-Rns`, `-Rs` or `-R`.
+    remove_flags: String,
+}
+
+impl PackageBackend for PacmanBackend {
+    fn name(&self) -> &str {
+        "pacman"
+    }
+
+    fn list_packages(&self) -> Vec<PackageVersionInfo> {
+        // Read the database directly, only shelling out to pacman if that fails.
+        database::get_all_packages(&self.db_path)
+            .unwrap_or_else(|_| commands::get_all_packages(self.name()))
+    }
+
+    fn get_details(&self, package_name: &str) -> PackageDetails {
+        commands::get_package_details(self.name(), package_name)
+    }
+
+    fn list_files(&self) -> HashMap<String, Vec<String>> {
+        database::get_all_files(&self.db_path)
+            .unwrap_or_else(|_| commands::get_all_files(self.name()))
+    }
+
+    fn history(&self) -> Result<Vec<HistoryEvent>, String> {
+        history::read_log(&self.log_path)
+            .map_err(|e| format!("Could not read {}: {}", self.log_path.display(), e))
+    }
+
+    fn database_stamp(&self) -> Option<u64> {
+        database::modified_stamp(&self.db_path)
+    }
+
+    fn database_paths(&self) -> Vec<PathBuf> {
+        // The root for `db.lck`, `local` for the package entries.
+        vec![self.db_path.clone(), self.db_path.join("local")]
+    }
+
+    fn database_locked(&self) -> bool {
+        Path::new(&self.db_path).join("db.lck").exists()
+    }
+
+    fn list_sync_packages(&self) -> Vec<SyncPackage> {
+        database::get_sync_packages(&self.db_path)
+            .unwrap_or_else(|_| commands::get_sync_packages(self.name()))
+    }
+
+    fn install_command(&self, package_names: &[String]) -> Vec<String> {
+        let mut command = vec![
+            self.name().to_string(),
+            "-S".to_string(),
+            "--noconfirm".to_string(),
+        ];
+        command.extend_from_slice(package_names);
+        command
+    }
+
+    fn install_preview(&self, package_names: &[String]) -> Result<Vec<String>, String> {
+        let mut args = vec!["-S", "--print-format", "%r/%n %v"];
+        args.extend(package_names.iter().map(|n| n.as_str()));
+        commands::print_transaction(self.name(), args)
+    }
+
+    fn install_files_command(&self, paths: &[String]) -> Vec<String> {
+        let mut command = vec![
+            self.name().to_string(),
+            "-U".to_string(),
+            "--noconfirm".to_string(),
+        ];
+        command.extend_from_slice(paths);
+        command
+    }
+
+    fn ignore_command(&self, package_name: &str) -> Vec<String> {
+        // pacman adds up every IgnorePkg line, so a new one can go straight under `[options]`.
+        vec![
+            "sed".to_string(),
+            "-i".to_string(),
+            format!("/^\\[options\\]/a IgnorePkg = {}", package_name),
+            self.conf_path.to_string_lossy().to_string(),
+        ]
+    }
+
+    fn is_ignored(&self, package_name: &str) -> bool {
+        fs::read_to_string(&self.conf_path)
+            .is_ok_and(|conf| ignored_packages(&conf).any(|name| name == package_name))
+    }
+
+    fn remove_command(&self, package_names: &[String]) -> Vec<String> {
+        let mut command = vec![
+            self.name().to_string(),
+            self.remove_flags.clone(),
+            "--noconfirm".to_string(),
+        ];
+        command.extend_from_slice(package_names);
+        command
+    }
+
+    fn upgrade_command(&self) -> Vec<String> {
+        vec![
+            self.name().to_string(),
+            "-Syu".to_string(),
+            "--noconfirm".to_string(),
+        ]
+    }
+
+    fn upgrade_preview(&self) -> Result<Vec<String>, String> {
+        commands::print_transaction(self.name(), vec!["-Su", "--print-format", "%r/%n %v"])
+    }
+
+    fn install_reason_command(&self, package_names: &[String], explicit: bool) -> Vec<String> {
+        let reason = if explicit { "--asexplicit" } else { "--asdeps" };
+        let mut command = vec![
+            self.name().to_string(),
+            "-D".to_string(),
+            reason.to_string(),
+        ];
+        command.extend_from_slice(package_names);
+        command
+    }
+
+    fn remove_preview(&self, package_names: &[String]) -> Result<Vec<String>, String> {
+        let mut args = vec![self.remove_flags.as_str(), "--print-format", "%n %v"];
+        args.extend(package_names.iter().map(|n| n.as_str()));
+        commands::print_transaction(self.name(), args)
+    }
+}
+
+/// Every package listed on an `IgnorePkg` line of a pacman.conf, there may be several.
+fn ignored_packages(conf: &str) -> impl Iterator<Item = &str> {
+    conf.lines()
+        .filter_map(|line| line.split('#').next()?.split_once('='))
+        .filter(|(key, _)| key.trim() == "IgnorePkg")
+        .flat_map(|(_, names)| names.split_whitespace())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_ignored_packages() {
+        let conf = "[options]\n\
+                    #IgnorePkg = commented\n\
+                    IgnorePkg = linux vim # pinned\n\
+                    HoldPkg = pacman\n\
+                    IgnorePkg=neovim\n";
+        let ignored: Vec<&str> = ignored_packages(conf).collect();
+        assert_eq!(ignored, ["linux", "vim", "neovim"]);
+    }
+}
@@ -0,0 +1,158 @@
+use std::{collections::HashMap, fs, io, path::PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::commands::{PackageDetails, PackageVersionInfo};
+
+/// Details of the installed packages keyed by name, so each package is only fetched once for
+/// as long as the package database doesn't change.
+///
+/// When `path` is set the cache is also kept on disk between runs, see `save`.
+pub struct DetailsCache {
+    entries: HashMap<String, CachedDetails>,
+    /// The current `PackageBackend::database_stamp`.
+    stamp: Option<u64>,
+    path: Option<PathBuf>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+struct CachedDetails {
+    details: PackageDetails,
+    /// The database stamp the details were fetched at. Details from before the database last
+    /// changed are still used, as only the installed version is ever cached, but fields like
+    /// "Required By" may be outdated, see `is_outdated`.
+    stamp: Option<u64>,
+}
+
+impl DetailsCache {
+    /// Loads the cache from `path`, if there is one. A missing or broken file is ignored.
+    ///
+    /// On disk the entries are keyed by `<name>-<version>` like the database itself, so only
+    /// the details of the installed version are ever used.
+    pub fn new(path: Option<PathBuf>) -> Self {
+        let entries = path
+            .as_ref()
+            .and_then(|p| fs::read_to_string(p).ok())
+            .and_then(|content| {
+                serde_json::from_str::<HashMap<String, CachedDetails>>(&content).ok()
+            })
+            .unwrap_or_default()
+            .into_values()
+            .map(|entry| (entry.details.name.clone(), entry))
+            .collect();
+
+        Self {
+            entries,
+            stamp: None,
+            path,
+        }
+    }
+
+    /// The database stamp of the last `update`.
+    pub fn stamp(&self) -> Option<u64> {
+        self.stamp
+    }
+
+    pub fn get(&self, name: &str) -> Option<&PackageDetails> {
+        self.entries.get(name).map(|e| &e.details)
+    }
+
+    /// Whether `name` has to be fetched because it isn't cached. Enough for fields that only
+    /// change with the version, like the size or the build date.
+    pub fn needs_fetch(&self, name: &str) -> bool {
+        !self.entries.contains_key(name)
+    }
+
+    /// Whether `name` was fetched before the database last changed, so "Required By",
+    /// "Optional For" and the install reason may be out of date. Also true if it isn't cached.
+    pub fn is_outdated(&self, name: &str) -> bool {
+        match self.entries.get(name) {
+            Some(entry) => entry.stamp.is_none() || entry.stamp != self.stamp,
+            None => true,
+        }
+    }
+
+    pub fn insert(&mut self, name: String, details: PackageDetails) {
+        let stamp = self.stamp;
+        self.entries.insert(name, CachedDetails { details, stamp });
+    }
+
+    /// Brings the cache in line with a fresh package list, taking any details the backend
+    /// already filled in. Entries for packages that are gone or were up/downgraded are dropped.
+    pub fn update(&mut self, packages: &mut [PackageVersionInfo], stamp: Option<u64>) {
+        self.stamp = stamp;
+
+        let installed: HashMap<&str, String> = packages
+            .iter()
+            .map(|p| (p.name.as_str(), p.version.to_string()))
+            .collect();
+        self.entries.retain(|name, entry| {
+            installed
+                .get(name.as_str())
+                .is_some_and(|version| *version == entry.details.version)
+        });
+
+        for package in packages.iter_mut() {
+            if let Some(details) = package.take_details() {
+                self.insert(package.name.clone(), details);
+            }
+        }
+    }
+
+    /// Writes the cache to disk, if it's enabled.
+    pub fn save(&self) -> io::Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+
+        let entries: HashMap<String, &CachedDetails> = self
+            .entries
+            .values()
+            .map(|e| (format!("{}-{}", e.details.name, e.details.version), e))
+            .collect();
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(path, serde_json::to_string(&entries)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::PackageType;
+
+    fn package(name: &str, version: &str) -> PackageVersionInfo {
+        let details = PackageDetails {
+            name: name.to_string(),
+            version: version.to_string(),
+            ..Default::default()
+        };
+        PackageVersionInfo::with_details(name.to_string(), PackageType::Explicit, details)
+    }
+
+    #[test]
+    fn reuses_entries_of_the_installed_version() {
+        let path = std::env::temp_dir().join(format!("pacmanman-cache-{}", std::process::id()));
+        let mut cache = DetailsCache::new(Some(path.clone()));
+        cache.update(
+            &mut [package("acl", "2.3.2-1"), package("glibc", "2.39-1")],
+            Some(1),
+        );
+        cache.save().unwrap();
+
+        // The database changed since, and glibc was upgraded.
+        let mut cache = DetailsCache::new(Some(path.clone()));
+        let mut packages = [package("acl", "2.3.2-1"), package("glibc", "2.40-1")];
+        packages.iter_mut().for_each(|p| drop(p.take_details()));
+        cache.update(&mut packages, Some(2));
+        fs::remove_file(&path).unwrap();
+
+        assert!(!cache.needs_fetch("acl"));
+        assert!(cache.is_outdated("acl"));
+        assert!(cache.needs_fetch("glibc"));
+
+        cache.insert("acl".to_string(), cache.get("acl").unwrap().clone());
+        assert!(!cache.is_outdated("acl"));
+    }
+}
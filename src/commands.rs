@@ -1,7 +1,16 @@
+use std::collections::HashMap;
 use std::process::Command;
+
+use chrono::{Local, NaiveDateTime, TimeZone};
+use serde::{Deserialize, Serialize};
+
+use crate::backend::PackageBackend;
+use crate::version::Version;
+
 #[derive(Clone)]
 pub struct PackageVersionInfo {
     pub name: String,
+    pub version: Version,
     details: Option<PackageDetails>,
     pub package_type: PackageType,
 }
@@ -13,11 +22,36 @@ impl PartialEq for PackageVersionInfo {
 }
 
 impl PackageVersionInfo {
-    pub fn get_details(&mut self) -> PackageDetails {
+    pub fn with_details(name: String, package_type: PackageType, details: PackageDetails) -> Self {
+        Self {
+            name,
+            version: Version::from(details.version.as_str()),
+            details: Some(details),
+            package_type,
+        }
+    }
+
+    /// Whether this is built from a version control checkout, e.g. `neovim-git`.
+    pub fn is_vcs(&self) -> bool {
+        ["-git", "-svn", "-hg", "-bzr", "-darcs", "-cvs", "-fossil"]
+            .iter()
+            .any(|suffix| self.name.ends_with(suffix))
+    }
+
+    /// Takes the details the backend filled in, see `DetailsCache::update`.
+    pub fn take_details(&mut self) -> Option<PackageDetails> {
+        self.details.take()
+    }
+
+    pub fn set_details(&mut self, details: PackageDetails) {
+        self.details = Some(details);
+    }
+
+    pub fn get_details(&mut self, backend: &dyn PackageBackend) -> PackageDetails {
         match &self.details {
             Some(d) => d.clone(),
             None => {
-                let details = get_package_details("pacman", &self.name);
+                let details = backend.get_details(&self.name);
                 self.details = Some(details.clone());
                 details
             }
@@ -25,31 +59,91 @@ impl PackageVersionInfo {
     }
 }
 
-#[derive(Clone, Default)]
+#[derive(Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
 pub struct PackageDetails {
+    /// Only known for packages from `-Si`.
+    pub repository: String,
     pub name: String,
     pub version: String,
     pub description: String,
+    pub architecture: String,
     pub url: String,
+    pub licenses: Vec<String>,
+    pub groups: Vec<String>,
+    pub provides: Vec<String>,
     pub depends_on: Vec<String>,
     pub optional_dependencies: Vec<String>,
     pub required_by: Vec<String>,
     pub optional_for: Vec<String>,
+    pub conflicts: Vec<String>,
+    pub replaces: Vec<String>,
     pub installed_size: String,
+    pub installed_bytes: u64,
+    pub packager: String,
+    /// Unix timestamps.
+    pub build_date: Option<i64>,
+    pub install_date: Option<i64>,
     pub installed_reason: String,
+    pub validated_by: Vec<String>,
+    /// Config files pacman won't overwrite on upgrade, e.g. `/etc/pacman.conf`.
+    pub backup_files: Vec<String>,
+}
+
+/// A package available in one of the sync repositories.
+#[derive(Clone, Default)]
+pub struct SyncPackage {
+    pub repo: String,
+    pub name: String,
+    pub version: Version,
+    pub description: String,
+    pub download_size: u64,
+    pub installed_size: u64,
+    pub depends_on: Vec<String>,
+    pub installed: bool,
+}
+
+/// An installed package with a newer version in the sync repositories.
+#[derive(Clone)]
+pub struct PackageUpdate {
+    pub name: String,
+    pub repo: String,
+    pub old_version: Version,
+    pub new_version: Version,
+    pub download_size: u64,
+    /// `None` until the details of the installed package are loaded.
+    pub old_installed_size: Option<u64>,
+    pub new_installed_size: u64,
+}
+
+impl PackageUpdate {
+    /// How much the installed size changes by, in bytes.
+    pub fn installed_size_change(&self) -> Option<i64> {
+        self.old_installed_size
+            .map(|old| self.new_installed_size as i64 - old as i64)
+    }
 }
 
 #[derive(Clone, PartialEq)]
 pub enum PackageType {
     Explicit,
+    /// Installed as a dependency and still needed by another package.
+    Dependency,
     Orphan,
     Foreign,
 }
 
-// TODO: Is there a better way than running the commands manually??
-// i.e. reading the info from a file.
+impl PackageType {
+    /// Installed as a dependency, like `pacman -Qd`, which lists the orphans too.
+    pub fn is_dependency(&self) -> bool {
+        matches!(self, PackageType::Dependency | PackageType::Orphan)
+    }
+}
+
+/// Fallback used when the pacman database can't be read directly, see `database::get_all_packages`.
 pub fn get_all_packages(package_manager: &str) -> Vec<PackageVersionInfo> {
     let mut list = get_explicit_packages(package_manager);
+    let dependencies = get_dependency_packages(package_manager);
     let orphans = get_orphan_packages(package_manager);
     let foreign = get_foreign_packages(package_manager);
 
@@ -65,6 +159,8 @@ pub fn get_all_packages(package_manager: &str) -> Vec<PackageVersionInfo> {
         })
         .collect();
 
+    // Orphans are dependencies too, only keep the orphan entry for those.
+    dedupe.extend(dependencies.into_iter().filter(|p| !orphans.contains(p)));
     dedupe.extend(orphans);
     dedupe.sort_by_key(|i| i.name.clone());
     dedupe
@@ -76,6 +172,12 @@ pub fn get_explicit_packages(package_manager: &str) -> Vec<PackageVersionInfo> {
     parse_version_list(&out, PackageType::Explicit)
 }
 
+pub fn get_dependency_packages(package_manager: &str) -> Vec<PackageVersionInfo> {
+    let out = run_command(package_manager, vec!["-Qd"]);
+
+    parse_version_list(&out, PackageType::Dependency)
+}
+
 pub fn get_orphan_packages(package_manager: &str) -> Vec<PackageVersionInfo> {
     let out = run_command(package_manager, vec!["-Qdt"]);
 
@@ -88,8 +190,49 @@ pub fn get_foreign_packages(package_manager: &str) -> Vec<PackageVersionInfo> {
     parse_version_list(&out, PackageType::Foreign)
 }
 
-fn get_package_details(package_manager: &str, package_name: &str) -> PackageDetails {
-    let out = run_command(package_manager, vec!["-Qi", package_name]);
+/// Fallback used when the sync databases can't be read directly, see
+/// `database::get_sync_packages`. Only the repo, name and version are known this way.
+pub fn get_sync_packages(package_manager: &str) -> Vec<SyncPackage> {
+    let out = run_command(package_manager, vec!["-Sl"]);
+
+    // Each line looks like `core acl 2.3.2-1 [installed]`.
+    out.lines()
+        .filter_map(|l| {
+            let split: Vec<&str> = l.split(' ').collect();
+            if split.len() < 3 {
+                return None;
+            }
+            Some(SyncPackage {
+                repo: split[0].to_string(),
+                name: split[1].to_string(),
+                version: Version::from(split[2]),
+                installed: split.len() > 3,
+                ..Default::default()
+            })
+        })
+        .collect()
+}
+
+/// Fallback used when the database can't be read directly, see `database::get_all_files`.
+pub fn get_all_files(package_manager: &str) -> HashMap<String, Vec<String>> {
+    let out = run_command(package_manager, vec!["-Ql"]);
+
+    // Each line looks like `acl /usr/bin/getfacl`.
+    let mut files: HashMap<String, Vec<String>> = HashMap::new();
+    for line in out.lines() {
+        if let Some((name, path)) = line.split_once(' ') {
+            files
+                .entry(name.to_string())
+                .or_default()
+                .push(path.to_string());
+        }
+    }
+    files
+}
+
+pub fn get_package_details(package_manager: &str, package_name: &str) -> PackageDetails {
+    // `-Qii` also lists the backup files.
+    let out = run_command(package_manager, vec!["-Qii", package_name]);
 
     parse_details_list(&out)
 }
@@ -106,6 +249,7 @@ fn parse_version_list(input: &str, package_type: PackageType) -> Vec<PackageVers
 
         version_list.push(PackageVersionInfo {
             name: split[0].to_string(),
+            version: Version::from(split[1]),
             details: None,
             package_type: package_type.clone(),
         });
@@ -113,26 +257,82 @@ fn parse_version_list(input: &str, package_type: PackageType) -> Vec<PackageVers
     version_list
 }
 
+/// Parses the output of `pacman -Qi` or `-Si` for a single package.
+///
+/// Each field starts with a `Label : value` line, with the labels padded so the values line up.
+/// Values that don't fit on one line continue on the following indented lines, and the backup
+/// files of `-Qii` are listed one per line without any indent.
 fn parse_details_list(input: &str) -> PackageDetails {
-    let lines: Vec<&str> = input.split("\n").collect();
-    let mut details: PackageDetails = PackageDetails::default();
-    for line in lines {
-        let split_line: Vec<&str> = line.split(":").collect();
-        let key = split_line[0].to_lowercase().replace(" ", "");
-
-        match key.as_ref() {
-            "name" => details.name = split_line[1].to_owned(),
-            "version" => details.version = split_line[1].to_owned(),
-            "description" => details.description = split_line[1].to_owned(),
-            "url" => details.url = split_line[1].to_owned(),
-            "dependson" => details.depends_on = vec![split_line[1].split("\n").collect()],
-            "optionaldeps" => {
-                details.optional_dependencies = vec![split_line[1].split("\n").collect()]
+    let mut fields: Vec<(&str, Vec<&str>)> = vec![];
+    for line in input.lines() {
+        match line.split_once(':') {
+            Some((label, value)) if is_label(label) => {
+                fields.push((label.trim(), vec![value.trim()]));
+            }
+            _ => {
+                if let Some((_, lines)) = fields.last_mut() {
+                    lines.push(line.trim());
+                }
+            }
+        }
+    }
+
+    let mut details = PackageDetails::default();
+    for (label, lines) in fields {
+        let lines: Vec<&str> = lines.into_iter().filter(|l| !l.is_empty()).collect();
+        if lines == ["None"] {
+            continue;
+        }
+        let text = lines.join(" ");
+        // List items are separated by two spaces, single spaces can be part of an item.
+        let list = || -> Vec<String> {
+            lines
+                .join("  ")
+                .split("  ")
+                .map(|v| v.trim().to_string())
+                .filter(|v| !v.is_empty())
+                .collect()
+        };
+
+        match label {
+            "Repository" => details.repository = text,
+            "Name" => details.name = text,
+            "Version" => details.version = text,
+            "Description" => details.description = text,
+            "Architecture" => details.architecture = text,
+            "URL" => details.url = text,
+            "Licenses" => details.licenses = list(),
+            "Groups" => details.groups = list(),
+            "Provides" => details.provides = list(),
+            "Depends On" => details.depends_on = list(),
+            "Optional Deps" => {
+                details.optional_dependencies = lines
+                    .iter()
+                    .map(|l| l.trim_end_matches("[installed]").trim_end().to_string())
+                    .collect()
+            }
+            "Required By" => details.required_by = list(),
+            "Optional For" => details.optional_for = list(),
+            "Conflicts With" => details.conflicts = list(),
+            "Replaces" => details.replaces = list(),
+            "Installed Size" => {
+                details.installed_bytes = parse_size(&text);
+                details.installed_size = text;
+            }
+            "Packager" => details.packager = text,
+            "Build Date" => details.build_date = parse_date(&text),
+            "Install Date" => details.install_date = parse_date(&text),
+            "Install Reason" => details.installed_reason = text,
+            // e.g. `MD5 Sum  SHA-256 Sum  Signature`.
+            "Validated By" => details.validated_by = list(),
+            "Backup Files" => {
+                details.backup_files = lines
+                    .iter()
+                    .filter(|l| **l != "(none)")
+                    // `-Qii` prefixes each file with its status, e.g. `UNMODIFIED\t/etc/foo`.
+                    .map(|l| l.rsplit('\t').next().unwrap_or_default().to_string())
+                    .collect()
             }
-            "requiredby" => details.required_by = vec![split_line[1].split(" ").collect()],
-            "optionalfor" => details.optional_for = vec![split_line[1].split(" ").collect()],
-            "installedsize" => details.installed_size = split_line[1].to_owned(),
-            "installreason" => details.installed_reason = split_line[1].to_owned(),
             _ => {}
         }
     }
@@ -140,11 +340,181 @@ fn parse_details_list(input: &str) -> PackageDetails {
     details
 }
 
+/// Whether `label` is the label of a `-Qi` field rather than part of a value, e.g. the
+/// `UNMODIFIED\t/etc/foo` backup file lines.
+fn is_label(label: &str) -> bool {
+    !label.starts_with(' ')
+        && label
+            .trim_end()
+            .chars()
+            .all(|c| c.is_ascii_alphabetic() || c == ' ')
+}
+
+/// Parses a date as pacman prints it in the C locale, e.g. `Tue Apr  2 10:00:00 2024`.
+fn parse_date(date: &str) -> Option<i64> {
+    let date = date.split_whitespace().collect::<Vec<_>>().join(" ");
+    let date = NaiveDateTime::parse_from_str(&date, "%a %b %d %H:%M:%S %Y").ok()?;
+    Local
+        .from_local_datetime(&date)
+        .earliest()
+        .map(|d| d.timestamp())
+}
+
+/// Formats a unix timestamp in the local timezone, e.g. `2024-04-02 10:00`.
+pub fn format_date(timestamp: i64) -> String {
+    Local
+        .timestamp_opt(timestamp, 0)
+        .single()
+        .map(|d| d.format("%Y-%m-%d %H:%M").to_string())
+        .unwrap_or_default()
+}
+
+/// Runs a transaction with `--print` so nothing is changed, returning the packages it would
+/// touch. This doesn't need root.
+pub fn print_transaction(
+    package_manager: &str,
+    mut args: Vec<&str>,
+) -> Result<Vec<String>, String> {
+    args.push("--print");
+    let output = Command::new(package_manager)
+        .args(args)
+        .output()
+        .map_err(|e| e.to_string())?;
+
+    if !output.status.success() {
+        return Err(String::from_utf8_lossy(&output.stderr).trim().to_string());
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter(|l| !l.is_empty())
+        .map(|l| l.to_string())
+        .collect())
+}
+
+/// Like `format_size` but with a sign, e.g. `+1.20 MiB` or `-339.04 KiB`.
+pub fn format_size_change(bytes: i64) -> String {
+    let sign = if bytes < 0 { "-" } else { "+" };
+    sign.to_owned() + &format_size(bytes.unsigned_abs())
+}
+
+/// Formats a size in bytes the same way pacman does, e.g. `339.04 KiB`.
+pub fn format_size(bytes: u64) -> String {
+    let units = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < units.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.2} {}", size, units[unit])
+}
+
+/// Reverses `format_size`, e.g. `339.04 KiB` -> 347176.
+pub fn parse_size(size: &str) -> u64 {
+    let units = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut split = size.split_whitespace();
+    let value: f64 = split.next().and_then(|v| v.parse().ok()).unwrap_or(0.0);
+    let unit = split
+        .next()
+        .and_then(|u| units.iter().position(|x| *x == u))
+        .unwrap_or(0);
+
+    (value * 1024_f64.powi(unit as i32)) as u64
+}
+
 fn run_command(package_manager: &str, args: Vec<&str>) -> String {
     let output = Command::new(package_manager)
         .args(args)
+        // The output is parsed, so it can't be translated.
+        .env("LC_ALL", "C")
         .output()
         .expect("Failed to execute command.");
 
     String::from_utf8(output.stdout).unwrap()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_local_package_details() {
+        let details = parse_details_list(include_str!("../tests/fixtures/qi_pacman.txt"));
+
+        assert_eq!(details.name, "pacman");
+        assert_eq!(details.version, "6.1.0-3");
+        assert_eq!(details.url, "https://www.archlinux.org/pacman/");
+        assert_eq!(details.licenses, ["GPL-2.0-or-later"]);
+        assert_eq!(details.groups, ["base-devel"]);
+        assert_eq!(details.provides, ["libalpm.so=14-64"]);
+        assert_eq!(details.depends_on.len(), 11);
+        assert_eq!(details.depends_on[5], "glibc>=2.26");
+        assert_eq!(details.depends_on[10], "systemd");
+        assert_eq!(
+            details.optional_dependencies,
+            [
+                "base-devel: required to use makepkg",
+                "perl-locale-gettext: translation support in makepkg-template"
+            ]
+        );
+        assert_eq!(details.required_by, ["base", "paru"]);
+        assert_eq!(details.installed_bytes, parse_size("4.78 MiB"));
+        assert_eq!(details.packager, "Morten Linderud <foxboron@archlinux.org>");
+        assert_eq!(details.installed_reason, "Explicitly installed");
+        assert_eq!(details.validated_by, ["SHA-256 Sum", "Signature"]);
+        assert_eq!(
+            details.backup_files,
+            ["/etc/pacman.conf", "/etc/makepkg.conf"]
+        );
+    }
+
+    #[test]
+    fn treats_none_as_empty() {
+        let details = parse_details_list(include_str!("../tests/fixtures/qi_pacman.txt"));
+
+        assert!(details.optional_for.is_empty());
+        assert!(details.conflicts.is_empty());
+        assert!(details.replaces.is_empty());
+    }
+
+    #[test]
+    fn parses_dates() {
+        let details = parse_details_list(include_str!("../tests/fixtures/qi_pacman.txt"));
+
+        let expected = |y, m, d, h, min, s| {
+            Local
+                .with_ymd_and_hms(y, m, d, h, min, s)
+                .earliest()
+                .map(|d| d.timestamp())
+        };
+        assert_eq!(details.build_date, expected(2024, 4, 2, 10, 0, 0));
+        assert_eq!(details.install_date, expected(2024, 4, 13, 21, 5, 9));
+    }
+
+    #[test]
+    fn parses_sync_package_details() {
+        let details = parse_details_list(include_str!("../tests/fixtures/si_python_pip.txt"));
+
+        assert_eq!(details.repository, "extra");
+        assert_eq!(details.version, "1:24.0-1");
+        assert_eq!(
+            details.description,
+            "The PyPA recommended tool for installing Python packages: pip"
+        );
+        assert_eq!(details.architecture, "any");
+        assert_eq!(
+            details.licenses,
+            ["MIT", "LicenseRef-custom AND Apache-2.0"]
+        );
+        assert!(details.groups.is_empty());
+        assert!(details.optional_dependencies.is_empty());
+        assert_eq!(details.replaces, ["python-pip-git"]);
+        assert!(details.install_date.is_none());
+        assert_eq!(
+            details.validated_by,
+            ["MD5 Sum", "SHA-256 Sum", "Signature"]
+        );
+        assert!(details.backup_files.is_empty());
+    }
+}
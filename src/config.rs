@@ -1,11 +1,19 @@
-use std::{error::Error, str::FromStr};
+use std::{error::Error, path::PathBuf, str::FromStr};
 
 use ratatui::style::Color;
 use serde::{Deserialize, Serialize};
 
 #[derive(Serialize, Deserialize)]
+#[serde(default)]
 pub struct ConfigFile {
+    // Name of the package manager backend to use, can be overridden with `--backend`.
+    backend: String,
+    // Used to run commands that need root, e.g. `sudo` or `doas`. Leave empty to run them as is.
+    privilege_command: String,
     theme: ConfigTheme,
+    list: ConfigList,
+    cache: ConfigCache,
+    pacman: ConfigPacman,
 }
 
 // TODO: Allow preset theme name or custom values?
@@ -21,8 +29,131 @@ struct ConfigTheme {
     selected_bg: String,
 }
 
+#[derive(Serialize, Deserialize)]
+#[serde(default)]
+struct ConfigList {
+    // Show each package's version next to its name.
+    show_version: bool,
+    // Put the best search matches first, otherwise keep the list's order.
+    rank_search_by_score: bool,
+    // Show the packages as a table with `columns` rather than a list of names.
+    table_view: bool,
+    // Columns of the table view in order, with their width in characters. `name` is one of
+    // name, version, type, size, repo and install_date.
+    columns: Vec<ConfigColumn>,
+    // Width of the package list in percent of the screen, in the list and the table view.
+    list_width: u16,
+    table_width: u16,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ConfigColumn {
+    name: String,
+    width: u16,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+#[serde(default)]
+struct ConfigCache {
+    // Keep package details in `$XDG_CACHE_HOME/pacmanman` between runs. Only worth it when
+    // the details come from `pacman -Qi` rather than the database.
+    details_on_disk: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(default)]
+struct ConfigPacman {
+    // Root of the pacman database, the local package entries live in `<db_path>/local`.
+    db_path: String,
+    // pacman's log, read to tell when packages were last upgraded.
+    log_path: String,
+    // Where downloaded packages are kept, searched for older versions to go back to.
+    cache_dirs: Vec<String>,
+    // pacman's config, packages are added to its IgnorePkg after downgrading them if asked to.
+    conf_path: String,
+    // Flags used to remove packages.
+    remove_flags: String,
+}
+
 pub struct Config {
+    pub backend: String,
+    pub privilege_command: String,
     pub theme: Theme,
+    pub show_version: bool,
+    pub rank_search_by_score: bool,
+    pub table_view: bool,
+    /// Every column in the order they're shown, including the hidden ones.
+    pub columns: Vec<TableColumn>,
+    pub list_width: u16,
+    pub table_width: u16,
+    /// Where to keep package details between runs, `None` to not keep them.
+    pub details_cache_path: Option<PathBuf>,
+    pub db_path: PathBuf,
+    pub log_path: PathBuf,
+    pub cache_dirs: Vec<PathBuf>,
+    pub conf_path: PathBuf,
+    pub remove_flags: String,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Column {
+    Name,
+    Version,
+    Type,
+    Size,
+    Repo,
+    InstallDate,
+}
+
+pub struct TableColumn {
+    pub column: Column,
+    pub width: u16,
+    pub shown: bool,
+}
+
+impl Column {
+    pub const ALL: [Column; 6] = [
+        Column::Name,
+        Column::Version,
+        Column::Type,
+        Column::Size,
+        Column::Repo,
+        Column::InstallDate,
+    ];
+
+    /// The name used in the config file.
+    fn key(&self) -> &'static str {
+        match self {
+            Column::Name => "name",
+            Column::Version => "version",
+            Column::Type => "type",
+            Column::Size => "size",
+            Column::Repo => "repo",
+            Column::InstallDate => "install_date",
+        }
+    }
+
+    pub fn title(&self) -> &'static str {
+        match self {
+            Column::Name => "Name",
+            Column::Version => "Version",
+            Column::Type => "Type",
+            Column::Size => "Size",
+            Column::Repo => "Repo",
+            Column::InstallDate => "Installed",
+        }
+    }
+
+    fn default_width(&self) -> u16 {
+        match self {
+            Column::Name => 30,
+            Column::Version => 20,
+            Column::Type => 10,
+            Column::Size => 11,
+            Column::Repo => 10,
+            Column::InstallDate => 16,
+        }
+    }
 }
 
 pub struct Theme {
@@ -39,7 +170,12 @@ pub struct Theme {
 impl Default for ConfigFile {
     fn default() -> Self {
         Self {
+            backend: "pacman".to_string(),
+            privilege_command: "sudo".to_string(),
             theme: ConfigTheme::default(),
+            list: ConfigList::default(),
+            cache: ConfigCache::default(),
+            pacman: ConfigPacman::default(),
         }
     }
 }
@@ -59,6 +195,37 @@ impl Default for ConfigTheme {
     }
 }
 
+impl Default for ConfigList {
+    fn default() -> Self {
+        Self {
+            show_version: false,
+            rank_search_by_score: true,
+            table_view: false,
+            columns: [Column::Name, Column::Version, Column::Type, Column::Size]
+                .iter()
+                .map(|c| ConfigColumn {
+                    name: c.key().to_string(),
+                    width: c.default_width(),
+                })
+                .collect(),
+            list_width: 10,
+            table_width: 50,
+        }
+    }
+}
+
+impl Default for ConfigPacman {
+    fn default() -> Self {
+        Self {
+            db_path: "/var/lib/pacman".to_string(),
+            log_path: "/var/log/pacman.log".to_string(),
+            cache_dirs: vec!["/var/cache/pacman/pkg".to_string()],
+            conf_path: "/etc/pacman.conf".to_string(),
+            remove_flags: "-Rns".to_string(),
+        }
+    }
+}
+
 impl ConfigFile {
     pub fn parse(self) -> Result<Config, Box<dyn Error>> {
         // TODO: Find a better way to map??
@@ -73,6 +240,111 @@ impl ConfigFile {
             selected_bg: Color::from_str(&self.theme.selected_bg)?,
         };
 
-        Ok(Config { theme })
+        let mut columns = vec![];
+        for column in &self.list.columns {
+            let kind = Column::ALL
+                .into_iter()
+                .find(|c| c.key() == column.name)
+                .ok_or(format!("Unknown column: {}", column.name))?;
+            columns.push(TableColumn {
+                column: kind,
+                // Same limits as resizing from the column menu.
+                width: column.width.clamp(3, 100),
+                shown: true,
+            });
+        }
+        // The hidden columns go last so they can be turned on from the column menu.
+        for column in Column::ALL {
+            if !columns.iter().any(|c| c.column == column) {
+                columns.push(TableColumn {
+                    column,
+                    width: column.default_width(),
+                    shown: false,
+                });
+            }
+        }
+
+        Ok(Config {
+            backend: self.backend,
+            privilege_command: self.privilege_command,
+            theme,
+            show_version: self.list.show_version,
+            rank_search_by_score: self.list.rank_search_by_score,
+            table_view: self.list.table_view,
+            columns,
+            // Same limits as resizing with `<` and `>`, the details need some room too.
+            list_width: self.list.list_width.clamp(10, 90),
+            table_width: self.list.table_width.clamp(10, 90),
+            details_cache_path: if self.cache.details_on_disk {
+                cache_dir().map(|d| d.join("details.json"))
+            } else {
+                None
+            },
+            db_path: PathBuf::from(self.pacman.db_path),
+            log_path: PathBuf::from(self.pacman.log_path),
+            cache_dirs: self.pacman.cache_dirs.iter().map(PathBuf::from).collect(),
+            conf_path: PathBuf::from(self.pacman.conf_path),
+            remove_flags: self.pacman.remove_flags,
+        })
+    }
+}
+
+impl Config {
+    /// Writes the view, columns and widths of the package list back to the config file,
+    /// leaving everything else in it as it is.
+    pub fn save_list_layout(&self) -> Result<(), confy::ConfyError> {
+        let mut file: ConfigFile = confy::load("pacmanman", None)?;
+        file.list.table_view = self.table_view;
+        file.list.columns = self
+            .columns
+            .iter()
+            .filter(|c| c.shown)
+            .map(|c| ConfigColumn {
+                name: c.column.key().to_string(),
+                width: c.width,
+            })
+            .collect();
+        file.list.list_width = self.list_width;
+        file.list.table_width = self.table_width;
+        confy::store("pacmanman", None, file)
+    }
+}
+
+/// `$XDG_CACHE_HOME/pacmanman`, falling back on `~/.cache/pacmanman`.
+fn cache_dir() -> Option<PathBuf> {
+    xdg_dir("XDG_CACHE_HOME", ".cache")
+}
+
+/// `$XDG_DATA_HOME/pacmanman`, falling back on `~/.local/share/pacmanman`.
+pub fn data_dir() -> Option<PathBuf> {
+    xdg_dir("XDG_DATA_HOME", ".local/share")
+}
+
+fn xdg_dir(var: &str, fallback: &str) -> Option<PathBuf> {
+    let base = std::env::var_os(var)
+        .filter(|d| !d.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|h| PathBuf::from(h).join(fallback)))?;
+    Some(base.join("pacmanman"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clamps_the_widths() {
+        let mut file = ConfigFile::default();
+        file.list.list_width = 0;
+        file.list.table_width = 100;
+        file.list.columns = vec![ConfigColumn {
+            name: "name".to_string(),
+            width: 0,
+        }];
+        let config = file.parse().unwrap();
+
+        assert_eq!(config.list_width, 10);
+        assert_eq!(config.table_width, 90);
+        assert_eq!(config.columns[0].width, 3);
     }
 }
@@ -0,0 +1,441 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    io::{self, Read},
+    path::Path,
+    time::UNIX_EPOCH,
+};
+
+use flate2::read::GzDecoder;
+
+use crate::commands::{format_size, PackageDetails, PackageType, PackageVersionInfo, SyncPackage};
+use crate::version::Version;
+
+/// Fields of a single `desc` entry, keyed by the `%NAME%` style header.
+type DescEntry = HashMap<String, Vec<String>>;
+
+/// Reads every installed package straight from the pacman database at `db_path`
+/// (normally `/var/lib/pacman`), without spawning pacman.
+///
+/// Classifies packages the same way as `pacman -Qe`, `-Qd`, `-Qdt` and `-Qm`. The details of
+/// each package are filled in the same pass.
+pub fn get_all_packages(db_path: &Path) -> io::Result<Vec<PackageVersionInfo>> {
+    let entries = read_local_entries(db_path)?;
+    let sync_names = read_sync_names(db_path);
+
+    let names: Vec<String> = entries.iter().map(|e| first_value(e, "NAME")).collect();
+    let providers = providers(
+        names
+            .iter()
+            .zip(&entries)
+            .map(|(name, entry)| (name.as_str(), values(entry, "PROVIDES"))),
+    );
+
+    let mut required_by: HashMap<String, Vec<String>> = HashMap::new();
+    let mut optional_for: HashMap<String, Vec<String>> = HashMap::new();
+    for entry in &entries {
+        let name = first_value(entry, "NAME");
+        for dep in values(entry, "DEPENDS") {
+            for provider in resolve(&providers, &strip_version(dep)) {
+                required_by.entry(provider).or_default().push(name.clone());
+            }
+        }
+        for dep in values(entry, "OPTDEPENDS") {
+            for provider in resolve(&providers, &strip_optional(dep)) {
+                optional_for.entry(provider).or_default().push(name.clone());
+            }
+        }
+    }
+
+    let mut list = vec![];
+    for entry in entries {
+        let name = first_value(&entry, "NAME");
+        let is_dependency = first_value(&entry, "REASON") == "1";
+        let required_by = required_by.remove(&name).unwrap_or_default();
+        let optional_for = optional_for.remove(&name).unwrap_or_default();
+
+        let package_type = if is_dependency {
+            // Same as `pacman -Qdt`, only dependencies nothing else needs are orphans.
+            if !required_by.is_empty() || !optional_for.is_empty() {
+                PackageType::Dependency
+            } else {
+                PackageType::Orphan
+            }
+        } else if sync_names.contains(&name) {
+            PackageType::Explicit
+        } else {
+            PackageType::Foreign
+        };
+
+        let installed_bytes = first_value(&entry, "SIZE").parse().unwrap_or(0);
+        let details = PackageDetails {
+            repository: String::new(),
+            name: name.clone(),
+            version: first_value(&entry, "VERSION"),
+            description: first_value(&entry, "DESC"),
+            architecture: first_value(&entry, "ARCH"),
+            url: first_value(&entry, "URL"),
+            licenses: values(&entry, "LICENSE").to_vec(),
+            groups: values(&entry, "GROUPS").to_vec(),
+            provides: values(&entry, "PROVIDES").to_vec(),
+            depends_on: values(&entry, "DEPENDS").to_vec(),
+            optional_dependencies: values(&entry, "OPTDEPENDS").to_vec(),
+            required_by: dedupe(required_by),
+            optional_for: dedupe(optional_for),
+            conflicts: values(&entry, "CONFLICTS").to_vec(),
+            replaces: values(&entry, "REPLACES").to_vec(),
+            installed_size: format_size(installed_bytes),
+            installed_bytes,
+            packager: first_value(&entry, "PACKAGER"),
+            build_date: first_value(&entry, "BUILDDATE").parse().ok(),
+            install_date: first_value(&entry, "INSTALLDATE").parse().ok(),
+            validated_by: values(&entry, "VALIDATION")
+                .iter()
+                .filter_map(|v| validation_name(v))
+                .collect(),
+            // Stored as `<path>\t<md5sum>`, relative to the root.
+            backup_files: values(&entry, "BACKUP")
+                .iter()
+                .map(|b| "/".to_owned() + b.split('\t').next().unwrap_or_default())
+                .collect(),
+            installed_reason: if is_dependency {
+                "Installed as a dependency for another package".to_string()
+            } else {
+                "Explicitly installed".to_string()
+            },
+        };
+
+        list.push(PackageVersionInfo::with_details(
+            name,
+            package_type,
+            details,
+        ));
+    }
+
+    list.sort_by_key(|i| i.name.clone());
+    Ok(list)
+}
+
+/// The latest modification time of the database root and its `local` directory, in
+/// nanoseconds. pacman creates and removes `db.lck` in the root for every transaction, and
+/// adds or removes a `local` entry for every package installed or removed.
+pub fn modified_stamp(db_path: &Path) -> Option<u64> {
+    [db_path.to_path_buf(), db_path.join("local")]
+        .iter()
+        .filter_map(|p| fs::metadata(p).and_then(|m| m.modified()).ok())
+        .max()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map(|d| d.as_nanos() as u64)
+}
+
+/// Reads every package available in the sync databases under `db_path` (`sync/*.db`).
+/// `installed` isn't filled in, the local database isn't read here.
+pub fn get_sync_packages(db_path: &Path) -> io::Result<Vec<SyncPackage>> {
+    let mut packages = vec![];
+    for file in fs::read_dir(db_path.join("sync"))? {
+        let path = file?.path();
+        if path.extension().and_then(|e| e.to_str()) != Some("db") {
+            continue;
+        }
+        let repo = path
+            .file_stem()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .to_string();
+
+        let mut archive = open_sync_db(&path)?;
+        for file in archive.entries()? {
+            let mut file = file?;
+            if !file.path()?.ends_with("desc") {
+                continue;
+            }
+            let mut content = String::new();
+            file.read_to_string(&mut content)?;
+
+            let entry = parse_desc(&content);
+            packages.push(SyncPackage {
+                repo: repo.clone(),
+                name: first_value(&entry, "NAME"),
+                version: Version::from(first_value(&entry, "VERSION").as_str()),
+                description: first_value(&entry, "DESC"),
+                download_size: first_value(&entry, "CSIZE").parse().unwrap_or(0),
+                installed_size: first_value(&entry, "ISIZE").parse().unwrap_or(0),
+                depends_on: values(&entry, "DEPENDS").to_vec(),
+                installed: false,
+            });
+        }
+    }
+
+    packages.sort_by(|a, b| a.name.cmp(&b.name).then(a.repo.cmp(&b.repo)));
+    Ok(packages)
+}
+
+/// Parses every `local/<name>-<version>/desc` file under the database root.
+fn read_local_entries(db_path: &Path) -> io::Result<Vec<DescEntry>> {
+    let mut entries = vec![];
+    for dir in fs::read_dir(db_path.join("local"))? {
+        let dir = dir?.path();
+        // Skip anything that isn't a package entry, e.g. the ALPM_DB_VERSION file.
+        let Ok(content) = fs::read_to_string(dir.join("desc")) else {
+            continue;
+        };
+
+        let mut entry = parse_desc(&content);
+        if !entry.contains_key("NAME") {
+            continue;
+        }
+        // The backup files are kept with the file list rather than in `desc`.
+        if let Ok(files) = fs::read_to_string(dir.join("files")) {
+            if let Some(backup) = parse_desc(&files).remove("BACKUP") {
+                entry.insert("BACKUP".to_string(), backup);
+            }
+        }
+        entries.push(entry);
+    }
+    Ok(entries)
+}
+
+/// Reads the files of every installed package from the `files` entries, keyed by package name.
+/// Paths are absolute and directories end with `/`, like `pacman -Ql`.
+pub fn get_all_files(db_path: &Path) -> io::Result<HashMap<String, Vec<String>>> {
+    let mut files = HashMap::new();
+    for dir in fs::read_dir(db_path.join("local"))? {
+        let dir = dir?.path();
+        let Ok(content) = fs::read_to_string(dir.join("files")) else {
+            continue;
+        };
+        let Some(name) = dir
+            .file_name()
+            .map(|n| strip_version_suffix(&n.to_string_lossy()))
+        else {
+            continue;
+        };
+
+        let paths = parse_desc(&content)
+            .remove("FILES")
+            .unwrap_or_default()
+            .into_iter()
+            .map(|f| "/".to_owned() + &f)
+            .collect();
+        files.insert(name, paths);
+    }
+    Ok(files)
+}
+
+/// Collects the names of every package available in the sync databases (`sync/*.db`).
+/// Databases that can't be read are ignored, as pacman does for `-Qm`.
+fn read_sync_names(db_path: &Path) -> HashSet<String> {
+    let mut names = HashSet::new();
+    let Ok(dir) = fs::read_dir(db_path.join("sync")) else {
+        return names;
+    };
+
+    for file in dir.flatten() {
+        let path = file.path();
+        if path.extension().and_then(|e| e.to_str()) != Some("db") {
+            continue;
+        }
+        if let Ok(file_names) = read_sync_db_names(&path) {
+            names.extend(file_names);
+        }
+    }
+    names
+}
+
+fn read_sync_db_names(path: &Path) -> io::Result<Vec<String>> {
+    let mut archive = open_sync_db(path)?;
+
+    let mut names = vec![];
+    for file in archive.entries()? {
+        let file = file?;
+        let path = file.path()?.to_string_lossy().to_string();
+        // Each package has a `<name>-<pkgver>-<pkgrel>/desc` entry.
+        if let Some(dir) = path.strip_suffix("/desc") {
+            names.push(strip_version_suffix(dir));
+        }
+    }
+    Ok(names)
+}
+
+fn open_sync_db(path: &Path) -> io::Result<tar::Archive<Box<dyn Read>>> {
+    let mut bytes = vec![];
+    fs::File::open(path)?.read_to_end(&mut bytes)?;
+
+    // Repo databases are gzip compressed by default but may also be a plain tarball.
+    Ok(if bytes.starts_with(&[0x1f, 0x8b]) {
+        tar::Archive::new(Box::new(GzDecoder::new(io::Cursor::new(bytes))))
+    } else {
+        tar::Archive::new(Box::new(io::Cursor::new(bytes)))
+    })
+}
+
+/// Splits a `desc` file into its `%FIELD%` sections.
+fn parse_desc(content: &str) -> DescEntry {
+    let mut entry = DescEntry::new();
+    let mut current: Option<String> = None;
+
+    for line in content.lines() {
+        if line.is_empty() {
+            current = None;
+        } else if let Some(key) = current.as_ref() {
+            entry.entry(key.clone()).or_default().push(line.to_string());
+        } else if line.starts_with('%') && line.ends_with('%') && line.len() > 1 {
+            let key = line.trim_matches('%').to_string();
+            entry.entry(key.clone()).or_default();
+            current = Some(key);
+        }
+    }
+
+    entry
+}
+
+fn values<'a>(entry: &'a DescEntry, key: &str) -> &'a [String] {
+    entry.get(key).map(|v| v.as_slice()).unwrap_or_default()
+}
+
+fn first_value(entry: &DescEntry, key: &str) -> String {
+    values(entry, key).first().cloned().unwrap_or_default()
+}
+
+/// Names a `%VALIDATION%` value the same way `pacman -Qi` does, `None` for `none`.
+fn validation_name(validation: &str) -> Option<String> {
+    let name = match validation {
+        "md5" => "MD5 Sum",
+        "sha256" => "SHA-256 Sum",
+        "pgp" => "Signature",
+        "none" => return None,
+        other => other,
+    };
+    Some(name.to_string())
+}
+
+fn resolve(providers: &HashMap<String, Vec<String>>, name: &str) -> Vec<String> {
+    providers.get(name).cloned().unwrap_or_default()
+}
+
+fn dedupe(mut names: Vec<String>) -> Vec<String> {
+    names.sort();
+    names.dedup();
+    names
+}
+
+/// Maps every name a package can be depended on by, itself and its provides, to the packages
+/// going by that name. Takes each package's name and `%PROVIDES%`.
+pub fn providers<'a>(
+    packages: impl IntoIterator<Item = (&'a str, &'a [String])>,
+) -> HashMap<String, Vec<String>> {
+    let mut providers: HashMap<String, Vec<String>> = HashMap::new();
+    for (name, provides) in packages {
+        providers
+            .entry(name.to_string())
+            .or_default()
+            .push(name.to_string());
+        for provide in provides {
+            providers
+                .entry(strip_version(provide))
+                .or_default()
+                .push(name.to_string());
+        }
+    }
+    providers
+}
+
+/// Strips the version constraint off a dependency, e.g. `glibc>=2.27` -> `glibc`.
+pub fn strip_version(dep: &str) -> String {
+    dep.split(['<', '>', '='])
+        .next()
+        .unwrap_or_default()
+        .to_string()
+}
+
+/// Strips the description off an optional dependency, e.g. `foo: for bar` -> `foo`.
+pub fn strip_optional(dep: &str) -> String {
+    strip_version(dep.split(':').next().unwrap_or_default())
+}
+
+/// Turns a `<name>-<pkgver>-<pkgrel>` directory name into the package name.
+fn strip_version_suffix(dir: &str) -> String {
+    dir.rsplitn(3, '-').last().unwrap_or_default().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn db_path() -> &'static Path {
+        Path::new("tests/fixtures")
+    }
+
+    #[test]
+    fn classifies_packages_like_pacman() {
+        let mut packages = get_all_packages(db_path()).unwrap();
+
+        let types: Vec<(&str, PackageType)> = packages
+            .iter()
+            .map(|p| (p.name.as_str(), p.package_type.clone()))
+            .collect();
+        assert!(
+            types
+                == [
+                    ("acl", PackageType::Explicit),
+                    ("foo", PackageType::Foreign),
+                    ("glibc", PackageType::Dependency),
+                    ("orph", PackageType::Orphan),
+                    // Only wanted optionally, which still keeps it from being an orphan.
+                    ("python-six", PackageType::Dependency),
+                ]
+        );
+
+        let glibc = packages[2].take_details().unwrap();
+        // acl depends on `libc.so`, which glibc provides.
+        assert_eq!(glibc.required_by, ["acl"]);
+        assert_eq!(glibc.backup_files, ["/etc/ld.so.conf"]);
+        assert_eq!(
+            glibc.installed_reason,
+            "Installed as a dependency for another package"
+        );
+
+        let python_six = packages[4].take_details().unwrap();
+        assert_eq!(python_six.optional_for, ["foo"]);
+        assert_eq!(python_six.version, "1.16.0-8");
+    }
+
+    #[test]
+    fn reads_the_files_and_sync_packages() {
+        let files = get_all_files(db_path()).unwrap();
+        assert_eq!(
+            files["python-six"],
+            ["/usr/", "/usr/lib/", "/usr/lib/six.py"]
+        );
+        assert_eq!(files.len(), 5);
+
+        let sync: Vec<String> = get_sync_packages(db_path())
+            .unwrap()
+            .iter()
+            .map(|p| format!("{}/{} {}", p.repo, p.name, p.version))
+            .collect();
+        assert_eq!(
+            sync,
+            [
+                "core/acl 2.3.2-1",
+                "core/glibc 2.39-1",
+                "core/python-six 1.16.0-8"
+            ]
+        );
+    }
+
+    #[test]
+    fn strips_the_version_off_entry_names() {
+        assert_eq!(strip_version_suffix("python-six-1.16.0-8"), "python-six");
+        assert_eq!(
+            strip_version_suffix("xorg-server-common-21.1.13-1"),
+            "xorg-server-common"
+        );
+        assert_eq!(strip_version_suffix("acl-2.3.2-1"), "acl");
+        assert_eq!(strip_version("libc.so>=6"), "libc.so");
+        assert_eq!(
+            strip_optional("python-six: for the python bindings"),
+            "python-six"
+        );
+    }
+}
@@ -0,0 +1,176 @@
+use std::{
+    collections::HashMap,
+    env, fs,
+    path::{Path, PathBuf},
+};
+
+/// A line of a package's file tree.
+pub struct FileRow {
+    /// Absolute, directories end with `/` like `pacman -Ql`.
+    pub path: String,
+    /// How deep the path is, e.g. 0 for `/usr/` and 1 for `/usr/bin/`.
+    pub depth: usize,
+    /// Size on disk in bytes, `None` for directories.
+    pub size: Option<u64>,
+    /// Listed in the package's backup array, these are usually config files.
+    pub backup: bool,
+    /// Gone from the disk, e.g. deleted by hand.
+    pub missing: bool,
+}
+
+impl FileRow {
+    pub fn is_dir(&self) -> bool {
+        self.path.ends_with('/')
+    }
+
+    /// The last part of the path, e.g. `bin/` or `ls`.
+    pub fn name(&self) -> &str {
+        let trimmed = self.path.trim_end_matches('/');
+        let start = trimmed.rfind('/').map(|i| i + 1).unwrap_or_default();
+        &self.path[start..]
+    }
+}
+
+/// Lays out the files of a package, as listed by `PackageBackend::list_files`, as a tree with
+/// each directory before its contents. Sizes are read from the disk.
+pub fn file_tree(paths: &[String], backup_files: &[String]) -> Vec<FileRow> {
+    let mut paths = paths.to_vec();
+    paths.sort();
+
+    paths
+        .into_iter()
+        .map(|path| {
+            let metadata = fs::symlink_metadata(&path).ok();
+            FileRow {
+                depth: path.trim_matches('/').matches('/').count(),
+                size: metadata
+                    .as_ref()
+                    .filter(|_| !path.ends_with('/'))
+                    .map(|m| m.len()),
+                backup: backup_files.contains(&path),
+                missing: metadata.is_none(),
+                path,
+            }
+        })
+        .collect()
+}
+
+/// Turns what was typed in the "who owns" prompt into an absolute path like `pacman -Qo` does.
+/// Relative paths are taken from the current directory, and a bare name is looked up in `PATH`
+/// if there is such a command.
+pub fn resolve_path(input: &str) -> PathBuf {
+    let path = Path::new(input);
+    let path = if path.is_absolute() {
+        path.to_path_buf()
+    } else {
+        let command = env::var_os("PATH")
+            .iter()
+            .flat_map(env::split_paths)
+            .map(|dir| dir.join(input))
+            .find(|p| !input.contains('/') && p.exists());
+        command.unwrap_or_else(|| env::current_dir().unwrap_or_default().join(path))
+    };
+    canonicalize_parent(path)
+}
+
+/// Resolves the symlinks in the directory part of `path`, e.g. `/bin/ls` becomes `/usr/bin/ls`
+/// since packages only list the real paths. The file itself is left alone, a symlink is owned
+/// by whichever package installed the link.
+fn canonicalize_parent(path: PathBuf) -> PathBuf {
+    let (Some(parent), Some(name)) = (path.parent(), path.file_name()) else {
+        return path;
+    };
+    match fs::canonicalize(parent) {
+        Ok(parent) => parent.join(name),
+        Err(_) => path,
+    }
+}
+
+/// Every package owning `path`, sorted. Only directories are owned by more than one package.
+pub fn owners(files: &HashMap<String, Vec<String>>, path: &Path) -> Vec<String> {
+    let path = path.to_string_lossy();
+    let path = path.trim_end_matches('/');
+
+    let mut owners: Vec<String> = files
+        .iter()
+        .filter(|(_, paths)| paths.iter().any(|p| p.trim_end_matches('/') == path))
+        .map(|(name, _)| name.clone())
+        .collect();
+    owners.sort();
+    owners
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builds_the_tree_with_sizes() {
+        let fixtures = format!("{}/tests/fixtures/", env!("CARGO_MANIFEST_DIR"));
+        let paths = vec![
+            fixtures.clone() + "pacman.log",
+            fixtures.clone() + "pkg/",
+            fixtures.clone(),
+            fixtures.clone() + "pkg/vim-9.1.0000-1-x86_64.pkg.tar.zst",
+            fixtures.clone() + "gone.conf",
+        ];
+        let rows = file_tree(&paths, &[fixtures.clone() + "gone.conf"]);
+
+        let names: Vec<&str> = rows.iter().map(|r| r.name()).collect();
+        assert_eq!(
+            names,
+            [
+                "fixtures/",
+                "gone.conf",
+                "pacman.log",
+                "pkg/",
+                "vim-9.1.0000-1-x86_64.pkg.tar.zst"
+            ]
+        );
+        assert_eq!(rows[1].depth, rows[0].depth + 1);
+        assert_eq!(rows[4].depth, rows[0].depth + 2);
+
+        assert!(rows[0].is_dir() && rows[0].size.is_none());
+        assert!(rows[1].backup && rows[1].missing);
+        assert_eq!(rows[4].size, Some(3));
+    }
+
+    #[test]
+    fn finds_the_owners() {
+        let files = HashMap::from([
+            (
+                "coreutils".to_string(),
+                vec![
+                    "/usr/".to_string(),
+                    "/usr/bin/".to_string(),
+                    "/usr/bin/ls".to_string(),
+                ],
+            ),
+            (
+                "pacman".to_string(),
+                vec!["/usr/".to_string(), "/usr/bin/pacman".to_string()],
+            ),
+        ]);
+
+        assert_eq!(owners(&files, Path::new("/usr/bin/ls")), ["coreutils"]);
+        assert_eq!(owners(&files, Path::new("/usr")), ["coreutils", "pacman"]);
+        assert!(owners(&files, Path::new("/usr/bin/vim")).is_empty());
+
+        // Like `/bin` being a link to `/usr/bin`.
+        let dir = env::temp_dir().join(format!("pacmanman-owners-{}", std::process::id()));
+        fs::create_dir_all(dir.join("usr/bin")).unwrap();
+        let bin = dir.join("bin");
+        let _ = fs::remove_file(&bin);
+        std::os::unix::fs::symlink(dir.join("usr/bin"), &bin).unwrap();
+        let ls = fs::canonicalize(&dir).unwrap().join("usr/bin/ls");
+        let files = HashMap::from([(
+            "coreutils".to_string(),
+            vec![ls.to_string_lossy().to_string()],
+        )]);
+
+        let path = resolve_path(&bin.join("ls").to_string_lossy());
+        assert_eq!(path, ls);
+        assert_eq!(owners(&files, &path), ["coreutils"]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
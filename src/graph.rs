@@ -0,0 +1,367 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use crate::backend::PackageBackend;
+use crate::commands::{PackageType, PackageVersionInfo};
+use crate::database::{providers, strip_optional, strip_version};
+
+/// The dependencies between every installed package, with virtual provides resolved to the
+/// packages providing them.
+pub struct DependencyGraph {
+    packages: HashMap<String, GraphPackage>,
+}
+
+struct GraphPackage {
+    package_type: PackageType,
+    depends: Vec<Link>,
+    required_by: Vec<Link>,
+}
+
+/// One side of a dependency between two packages.
+#[derive(Clone)]
+pub struct Link {
+    /// The installed package, or the dependency as written if nothing installed satisfies it.
+    pub name: String,
+    /// The virtual package the link was resolved through, e.g. `libc.so` for glibc.
+    pub provides: Option<String>,
+    pub optional: bool,
+    pub installed: bool,
+}
+
+/// A row of the flattened dependency tree.
+pub struct TreeRow {
+    pub depth: usize,
+    /// The names from the root down to this row, used to remember which rows are expanded.
+    pub path: Vec<String>,
+    pub link: Link,
+    /// The package is already one of the row's ancestors, so it isn't expanded again.
+    pub cycle: bool,
+    pub has_children: bool,
+}
+
+impl TreeRow {
+    pub fn name(&self) -> &str {
+        &self.link.name
+    }
+}
+
+impl DependencyGraph {
+    /// Builds the graph, fetching the details of any package that doesn't have them yet.
+    pub fn build(packages: &mut [PackageVersionInfo], backend: &dyn PackageBackend) -> Self {
+        let details: Vec<_> = packages
+            .iter_mut()
+            .map(|p| {
+                (
+                    p.name.clone(),
+                    p.package_type.clone(),
+                    p.get_details(backend),
+                )
+            })
+            .collect();
+
+        let providers = providers(
+            details
+                .iter()
+                .map(|(name, _, d)| (name.as_str(), d.provides.as_slice())),
+        );
+
+        let resolve = |dep: String, optional: bool| -> Vec<Link> {
+            let Some(names) = providers.get(&dep) else {
+                return vec![Link {
+                    name: dep,
+                    provides: None,
+                    optional,
+                    installed: false,
+                }];
+            };
+            names
+                .iter()
+                .map(|name| Link {
+                    name: name.clone(),
+                    provides: (*name != dep).then(|| dep.clone()),
+                    optional,
+                    installed: true,
+                })
+                .collect()
+        };
+
+        let mut packages: HashMap<String, GraphPackage> = HashMap::new();
+        for (name, package_type, d) in &details {
+            let mut depends: Vec<Link> = vec![];
+            for dep in &d.depends_on {
+                depends.extend(resolve(strip_version(dep), false));
+            }
+            // Optional dependencies only matter once they're installed.
+            for dep in &d.optional_dependencies {
+                depends.extend(
+                    resolve(strip_optional(dep), true)
+                        .into_iter()
+                        .filter(|l| l.installed),
+                );
+            }
+            dedupe(&mut depends);
+
+            packages.insert(
+                name.clone(),
+                GraphPackage {
+                    package_type: package_type.clone(),
+                    depends,
+                    required_by: vec![],
+                },
+            );
+        }
+
+        let mut required_by: HashMap<String, Vec<Link>> = HashMap::new();
+        for (name, package) in &packages {
+            for link in package.depends.iter().filter(|l| l.installed) {
+                required_by
+                    .entry(link.name.clone())
+                    .or_default()
+                    .push(Link {
+                        name: name.clone(),
+                        ..link.clone()
+                    });
+            }
+        }
+        for (name, mut links) in required_by {
+            if let Some(package) = packages.get_mut(&name) {
+                dedupe(&mut links);
+                package.required_by = links;
+            }
+        }
+
+        Self { packages }
+    }
+
+    pub fn package_type(&self, name: &str) -> Option<&PackageType> {
+        self.packages.get(name).map(|p| &p.package_type)
+    }
+
+    /// The dependencies of `name`, or the packages depending on it if `reverse` is set.
+    pub fn links(&self, name: &str, reverse: bool) -> &[Link] {
+        match self.packages.get(name) {
+            Some(p) if reverse => &p.required_by,
+            Some(p) => &p.depends,
+            None => &[],
+        }
+    }
+
+    /// The chains of packages pulling `name` in, each ending with an explicitly installed package,
+    /// e.g. `[bar, baz]` for `libfoo ← bar ← baz`. Only the shortest chain to each explicit
+    /// package is kept, and chains that need an optional dependency come last.
+    pub fn why_installed(&self, name: &str) -> Vec<Vec<Link>> {
+        let mut chains = self.chains(name, false);
+        let explicit: HashSet<String> = chains
+            .iter()
+            .filter_map(|c| c.last())
+            .map(|l| l.name.clone())
+            .collect();
+        chains.extend(
+            self.chains(name, true)
+                .into_iter()
+                .filter(|c| c.last().is_some_and(|l| !explicit.contains(&l.name))),
+        );
+        chains
+    }
+
+    /// Walks the reverse dependencies of `name` breadth first, stopping at explicitly installed
+    /// packages so each chain is as short as possible.
+    fn chains(&self, name: &str, optional: bool) -> Vec<Vec<Link>> {
+        let is_explicit = |name: &str| {
+            matches!(
+                self.package_type(name),
+                Some(PackageType::Explicit) | Some(PackageType::Foreign)
+            )
+        };
+
+        // Which package and link each visited package was reached from.
+        let mut parents: HashMap<String, (String, Link)> = HashMap::new();
+        let mut visited = HashSet::from([name.to_string()]);
+        let mut queue = VecDeque::from([name.to_string()]);
+        let mut roots = vec![];
+        while let Some(current) = queue.pop_front() {
+            for link in self.links(&current, true) {
+                if (link.optional && !optional) || !visited.insert(link.name.clone()) {
+                    continue;
+                }
+                parents.insert(link.name.clone(), (current.clone(), link.clone()));
+                if is_explicit(&link.name) {
+                    roots.push(link.name.clone());
+                } else {
+                    queue.push_back(link.name.clone());
+                }
+            }
+        }
+
+        roots
+            .into_iter()
+            .map(|root| {
+                let mut chain = vec![];
+                let mut current = root;
+                while let Some((parent, link)) = parents.get(&current) {
+                    chain.push(link.clone());
+                    current = parent.clone();
+                }
+                chain.reverse();
+                chain
+            })
+            .collect()
+    }
+
+    /// Flattens the tree under `root`, only descending into the rows whose path is `expanded`.
+    pub fn tree(&self, root: &str, reverse: bool, expanded: &HashSet<Vec<String>>) -> Vec<TreeRow> {
+        let root = Link {
+            name: root.to_string(),
+            provides: None,
+            optional: false,
+            installed: self.packages.contains_key(root),
+        };
+
+        let mut rows = vec![];
+        self.push_rows(vec![root.name.clone()], root, reverse, expanded, &mut rows);
+        rows
+    }
+
+    fn push_rows(
+        &self,
+        path: Vec<String>,
+        link: Link,
+        reverse: bool,
+        expanded: &HashSet<Vec<String>>,
+        rows: &mut Vec<TreeRow>,
+    ) {
+        let cycle = path[..path.len() - 1].contains(&link.name);
+        let children = self.links(&link.name, reverse);
+        let has_children = !cycle && !children.is_empty();
+        let is_expanded = has_children && expanded.contains(&path);
+
+        rows.push(TreeRow {
+            depth: path.len() - 1,
+            path: path.clone(),
+            link,
+            cycle,
+            has_children,
+        });
+
+        if is_expanded {
+            for child in children {
+                let mut child_path = path.clone();
+                child_path.push(child.name.clone());
+                self.push_rows(child_path, child.clone(), reverse, expanded, rows);
+            }
+        }
+    }
+}
+
+/// Sorts the links by name, keeping a hard dependency over an optional one on the same package.
+fn dedupe(links: &mut Vec<Link>) {
+    links.sort_by(|a, b| a.name.cmp(&b.name).then(a.optional.cmp(&b.optional)));
+    links.dedup_by(|a, b| a.name == b.name);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend;
+    use crate::commands::PackageDetails;
+    use crate::config::ConfigFile;
+
+    fn package(name: &str, package_type: PackageType, depends: &[&str]) -> PackageVersionInfo {
+        let to_vec = |v: &[&str]| v.iter().map(|s| s.to_string()).collect();
+        let details = PackageDetails {
+            name: name.to_string(),
+            version: "1.0-1".to_string(),
+            depends_on: to_vec(depends),
+            ..Default::default()
+        };
+        PackageVersionInfo::with_details(name.to_string(), package_type, details)
+    }
+
+    /// `app` needs `bar` and `baz`, which need each other. `bar` needs glibc through `libc.so`,
+    /// `tool` only wants glibc optionally.
+    fn graph() -> DependencyGraph {
+        let mut glibc = package("glibc", PackageType::Dependency, &[]);
+        let mut details = glibc.take_details().unwrap();
+        details.provides = vec!["libc.so=6-64".to_string()];
+        glibc.set_details(details);
+
+        let mut tool = package("tool", PackageType::Explicit, &[]);
+        let mut details = tool.take_details().unwrap();
+        details.optional_dependencies = vec!["glibc: for everything".to_string()];
+        tool.set_details(details);
+
+        let mut packages = vec![
+            glibc,
+            tool,
+            package("bar", PackageType::Dependency, &["libc.so", "baz"]),
+            package("baz", PackageType::Dependency, &["bar>=1.0"]),
+            package("app", PackageType::Explicit, &["bar", "baz", "missing"]),
+        ];
+        // The details are all there, so the backend is never used.
+        let config = ConfigFile::default().parse().unwrap();
+        let backend = backend::from_name("pacman", &config).unwrap();
+        DependencyGraph::build(&mut packages, backend.as_ref())
+    }
+
+    fn names(links: &[Link]) -> Vec<&str> {
+        links.iter().map(|l| l.name.as_str()).collect()
+    }
+
+    #[test]
+    fn resolves_virtual_provides() {
+        let graph = graph();
+
+        let depends = graph.links("bar", false);
+        assert_eq!(names(depends), ["baz", "glibc"]);
+        assert_eq!(depends[1].provides.as_deref(), Some("libc.so"));
+
+        let missing = &graph.links("app", false)[2];
+        assert_eq!(missing.name, "missing");
+        assert!(!missing.installed);
+    }
+
+    #[test]
+    fn marks_cycles_without_expanding_them() {
+        let graph = graph();
+        let expanded = HashSet::from([
+            vec!["bar".to_string()],
+            vec!["bar".to_string(), "baz".to_string()],
+            vec!["bar".to_string(), "baz".to_string(), "bar".to_string()],
+        ]);
+
+        let rows = graph.tree("bar", false, &expanded);
+        let rows: Vec<(usize, &str, bool)> =
+            rows.iter().map(|r| (r.depth, r.name(), r.cycle)).collect();
+        assert_eq!(
+            rows,
+            [
+                (0, "bar", false),
+                (1, "baz", false),
+                (2, "bar", true),
+                (1, "glibc", false)
+            ]
+        );
+    }
+
+    #[test]
+    fn builds_the_reverse_tree() {
+        let graph = graph();
+        let rows = graph.tree("glibc", true, &HashSet::from([vec!["glibc".to_string()]]));
+
+        let names: Vec<&str> = rows.iter().map(|r| r.name()).collect();
+        assert_eq!(names, ["glibc", "bar", "tool"]);
+        assert!(rows[1].has_children && !rows[1].link.optional);
+        assert!(rows[2].link.optional);
+    }
+
+    #[test]
+    fn explains_why_a_package_is_installed() {
+        let graph = graph();
+
+        // Straight from bar to app rather than through baz, and the optional chain last.
+        let chains = graph.why_installed("glibc");
+        let chains: Vec<Vec<&str>> = chains.iter().map(|c| names(c)).collect();
+        assert_eq!(chains, [vec!["bar", "app"], vec!["tool"]]);
+
+        assert!(graph.why_installed("app").is_empty());
+    }
+}
@@ -0,0 +1,415 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs, io,
+    path::Path,
+};
+
+use chrono::{DateTime, Local, NaiveDateTime, TimeZone};
+
+use crate::pkgcache::{self, CachedPackage};
+
+/// A line of pacman's log, e.g. `[2024-04-02T10:00:06+0200] [ALPM] upgraded pacman (...)`.
+#[derive(Clone)]
+pub struct HistoryEvent {
+    /// Unix timestamp.
+    pub timestamp: i64,
+    pub kind: EventKind,
+}
+
+#[derive(Clone, PartialEq)]
+pub enum EventKind {
+    Installed {
+        name: String,
+        version: String,
+    },
+    Upgraded {
+        name: String,
+        old_version: String,
+        new_version: String,
+    },
+    Downgraded {
+        name: String,
+        old_version: String,
+        new_version: String,
+    },
+    Removed {
+        name: String,
+        version: String,
+    },
+    Reinstalled {
+        name: String,
+        version: String,
+    },
+    TransactionStarted,
+    TransactionCompleted,
+    /// `transaction failed`, or `transaction interrupted` by older versions of pacman.
+    TransactionFailed,
+    /// The command pacman was run with, e.g. `pacman -Syu`.
+    Command(String),
+    Warning(String),
+    /// The hook that ran, e.g. `30-systemd-update.hook`.
+    Hook(String),
+}
+
+impl HistoryEvent {
+    /// The package the event is about, if it's about one.
+    pub fn package(&self) -> Option<&str> {
+        match &self.kind {
+            EventKind::Installed { name, .. }
+            | EventKind::Upgraded { name, .. }
+            | EventKind::Downgraded { name, .. }
+            | EventKind::Removed { name, .. }
+            | EventKind::Reinstalled { name, .. } => Some(name),
+            _ => None,
+        }
+    }
+}
+
+impl EventKind {
+    /// What happened, e.g. `upgraded`, as pacman logs it.
+    pub fn action(&self) -> &'static str {
+        match self {
+            EventKind::Installed { .. } => "installed",
+            EventKind::Upgraded { .. } => "upgraded",
+            EventKind::Downgraded { .. } => "downgraded",
+            EventKind::Removed { .. } => "removed",
+            EventKind::Reinstalled { .. } => "reinstalled",
+            EventKind::TransactionStarted => "transaction started",
+            EventKind::TransactionCompleted => "transaction completed",
+            EventKind::TransactionFailed => "transaction failed",
+            EventKind::Command(_) => "ran",
+            EventKind::Warning(_) => "warning",
+            EventKind::Hook(_) => "hook",
+        }
+    }
+
+    /// Everything but the action, e.g. `pacman 6.0.2-9 -> 6.1.0-3`.
+    pub fn subject(&self) -> String {
+        match self {
+            EventKind::Installed { name, version }
+            | EventKind::Removed { name, version }
+            | EventKind::Reinstalled { name, version } => format!("{} {}", name, version),
+            EventKind::Upgraded {
+                name,
+                old_version,
+                new_version,
+            }
+            | EventKind::Downgraded {
+                name,
+                old_version,
+                new_version,
+            } => format!("{} {} -> {}", name, old_version, new_version),
+            EventKind::TransactionStarted
+            | EventKind::TransactionCompleted
+            | EventKind::TransactionFailed => String::new(),
+            EventKind::Command(text) | EventKind::Warning(text) | EventKind::Hook(text) => {
+                text.clone()
+            }
+        }
+    }
+}
+
+/// The packages changed between `transaction started` and the end of the transaction.
+#[derive(Clone)]
+pub struct PastTransaction {
+    /// When the transaction started, as a unix timestamp.
+    pub timestamp: i64,
+    /// The command that started it, e.g. `pacman -Syu`, if it was logged.
+    pub command: Option<String>,
+    /// Only the package changes, in the order they were made.
+    pub changes: Vec<HistoryEvent>,
+    /// `false` if the transaction failed or the log ends before it did.
+    pub completed: bool,
+}
+
+/// What it takes to undo a transaction.
+pub struct Rollback {
+    /// The cached packages to install, one for each package that was upgraded, downgraded or
+    /// removed.
+    pub files: Vec<CachedPackage>,
+    /// The versions to go back to that aren't in the cache, as `name version`.
+    pub missing: Vec<String>,
+    /// Packages the transaction installed. Rolling back leaves them installed.
+    pub installed: Vec<String>,
+    /// Packages the transaction removed. They come back as explicitly installed, the log
+    /// doesn't say if they were dependencies.
+    pub removed: Vec<String>,
+}
+
+impl PastTransaction {
+    /// Finds the files in `cache` that put every package back to the version it had before the
+    /// transaction.
+    pub fn rollback(&self, cache: &[CachedPackage]) -> Rollback {
+        let mut rollback = Rollback {
+            files: vec![],
+            missing: vec![],
+            installed: vec![],
+            removed: vec![],
+        };
+        let mut seen = HashSet::new();
+        for change in &self.changes {
+            let Some(name) = change.package() else {
+                continue;
+            };
+            // The first change of a package has the version it had before.
+            if !seen.insert(name) {
+                continue;
+            }
+
+            let version = match &change.kind {
+                EventKind::Upgraded { old_version, .. }
+                | EventKind::Downgraded { old_version, .. } => old_version,
+                EventKind::Removed { version, .. } => {
+                    rollback.removed.push(name.to_string());
+                    version
+                }
+                EventKind::Installed { .. } => {
+                    rollback.installed.push(name.to_string());
+                    continue;
+                }
+                _ => continue,
+            };
+            match pkgcache::find(cache, name, version) {
+                Some(package) => rollback.files.push(package.clone()),
+                None => rollback.missing.push(format!("{} {}", name, version)),
+            }
+        }
+        rollback
+    }
+}
+
+pub fn read_log(log_path: &Path) -> io::Result<Vec<HistoryEvent>> {
+    Ok(parse_log(&fs::read_to_string(log_path)?))
+}
+
+/// Parses the lines of pacman's log that are worth showing, oldest first. Anything else, like
+/// the output of scriptlets and hooks, is skipped.
+pub fn parse_log(content: &str) -> Vec<HistoryEvent> {
+    content.lines().filter_map(parse_line).collect()
+}
+
+fn parse_line(line: &str) -> Option<HistoryEvent> {
+    let (date, rest) = line.strip_prefix('[')?.split_once("] ")?;
+    let timestamp = parse_log_date(date)?;
+
+    let kind = if let Some(command) = rest.strip_prefix("[PACMAN] Running ") {
+        EventKind::Command(command.trim_matches('\'').to_string())
+    } else {
+        parse_alpm(rest.strip_prefix("[ALPM] ")?)?
+    };
+    Some(HistoryEvent { timestamp, kind })
+}
+
+fn parse_alpm(message: &str) -> Option<EventKind> {
+    match message {
+        "transaction started" => return Some(EventKind::TransactionStarted),
+        "transaction completed" => return Some(EventKind::TransactionCompleted),
+        "transaction failed" | "transaction interrupted" => {
+            return Some(EventKind::TransactionFailed)
+        }
+        _ => {}
+    }
+    if let Some(warning) = message.strip_prefix("warning: ") {
+        return Some(EventKind::Warning(warning.to_string()));
+    }
+    if let Some(hook) = message.strip_prefix("running '") {
+        return Some(EventKind::Hook(hook.trim_end_matches("'...").to_string()));
+    }
+
+    // e.g. `upgraded pacman (6.0.2-9 -> 6.1.0-3)` or `installed vim (9.1.0000-1)`
+    let (action, rest) = message.split_once(' ')?;
+    let (name, versions) = rest.split_once(" (")?;
+    let name = name.to_string();
+    let versions = versions.strip_suffix(')')?;
+    let (old_version, new_version) = versions
+        .split_once(" -> ")
+        .map(|(old, new)| (old.to_string(), new.to_string()))
+        .unwrap_or_default();
+    let version = versions.to_string();
+
+    Some(match action {
+        "installed" => EventKind::Installed { name, version },
+        "removed" => EventKind::Removed { name, version },
+        "reinstalled" => EventKind::Reinstalled { name, version },
+        "upgraded" => EventKind::Upgraded {
+            name,
+            old_version,
+            new_version,
+        },
+        "downgraded" => EventKind::Downgraded {
+            name,
+            old_version,
+            new_version,
+        },
+        _ => return None,
+    })
+}
+
+/// Groups the package changes in `events` by the transaction that made them, oldest first.
+pub fn transactions(events: &[HistoryEvent]) -> Vec<PastTransaction> {
+    let mut transactions = vec![];
+    let mut command = None;
+    let mut current: Option<PastTransaction> = None;
+
+    for event in events {
+        match &event.kind {
+            EventKind::Command(text) => command = Some(text.clone()),
+            EventKind::TransactionStarted => {
+                // The last one never finished, e.g. pacman was killed.
+                transactions.extend(current.take());
+                current = Some(PastTransaction {
+                    timestamp: event.timestamp,
+                    command: command.take(),
+                    changes: vec![],
+                    completed: false,
+                });
+            }
+            EventKind::TransactionCompleted | EventKind::TransactionFailed => {
+                if let Some(mut transaction) = current.take() {
+                    transaction.completed = event.kind == EventKind::TransactionCompleted;
+                    transactions.push(transaction);
+                }
+            }
+            _ => {
+                if let (Some(transaction), Some(_)) = (current.as_mut(), event.package()) {
+                    transaction.changes.push(event.clone());
+                }
+            }
+        }
+    }
+    transactions.extend(current);
+    transactions
+}
+
+/// When each package was last upgraded or downgraded, as a unix timestamp. Packages that were
+/// only ever installed aren't included.
+pub fn last_upgrades(events: &[HistoryEvent]) -> HashMap<String, i64> {
+    events
+        .iter()
+        .filter_map(|e| match &e.kind {
+            EventKind::Upgraded { name, .. } | EventKind::Downgraded { name, .. } => {
+                Some((name.clone(), e.timestamp))
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+/// Parses the dates pacman writes to its log, `2024-04-02T10:00:00+0200` since pacman 5.2 and
+/// `2019-04-02 10:00` in the local timezone before that.
+fn parse_log_date(date: &str) -> Option<i64> {
+    if let Ok(date) = DateTime::parse_from_str(date, "%Y-%m-%dT%H:%M:%S%z") {
+        return Some(date.timestamp());
+    }
+    let date = NaiveDateTime::parse_from_str(date, "%Y-%m-%d %H:%M").ok()?;
+    Local
+        .from_local_datetime(&date)
+        .earliest()
+        .map(|d| d.timestamp())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    fn fixture() -> Vec<HistoryEvent> {
+        parse_log(include_str!("../tests/fixtures/pacman.log"))
+    }
+
+    #[test]
+    fn parses_every_kind_of_event() {
+        let events = fixture();
+
+        let actions: Vec<&str> = events.iter().map(|e| e.kind.action()).collect();
+        assert_eq!(
+            actions,
+            [
+                "ran",
+                "transaction started",
+                "installed",
+                "transaction completed",
+                "ran",
+                "transaction started",
+                "upgraded",
+                "warning",
+                "upgraded",
+                "removed",
+                "hook",
+                "transaction completed",
+                "ran",
+                "transaction started",
+                "downgraded",
+                "transaction completed",
+                "ran",
+                "transaction started",
+                "reinstalled",
+                "transaction completed",
+            ]
+        );
+
+        assert!(events[4].kind == EventKind::Command("pacman -Syu".to_string()));
+        assert!(
+            events[6].kind
+                == EventKind::Upgraded {
+                    name: "pacman".to_string(),
+                    old_version: "6.0.2-9".to_string(),
+                    new_version: "6.1.0-3".to_string(),
+                }
+        );
+        assert_eq!(events[6].timestamp, 1712044806);
+        assert_eq!(
+            events[7].kind.subject(),
+            "/etc/pacman.conf installed as /etc/pacman.conf.pacnew"
+        );
+        assert_eq!(events[9].kind.subject(), "python-six 1.16.0-8");
+        assert_eq!(events[10].kind.subject(), "30-systemd-update.hook");
+        assert_eq!(events[18].package(), Some("pacman"));
+    }
+
+    #[test]
+    fn finds_the_last_upgrades() {
+        let upgrades = last_upgrades(&fixture());
+
+        assert_eq!(upgrades.len(), 2);
+        assert_eq!(upgrades["pacman"], 1712044806);
+        // The downgrade a day later.
+        assert_eq!(upgrades["vim"], 1712161802);
+    }
+
+    #[test]
+    fn groups_and_rolls_back_transactions() {
+        let transactions = transactions(&fixture());
+        assert_eq!(transactions.len(), 4);
+
+        let upgrade = &transactions[1];
+        assert_eq!(upgrade.command.as_deref(), Some("pacman -Syu"));
+        assert_eq!(upgrade.timestamp, 1712044805);
+        assert_eq!(upgrade.changes.len(), 3);
+        assert!(upgrade.completed);
+
+        let cache = pkgcache::scan(&[PathBuf::from("tests/fixtures/pkg")]);
+        let rollback = upgrade.rollback(&cache);
+        let files: Vec<String> = rollback
+            .files
+            .iter()
+            .map(|f| f.path.file_name().unwrap().to_string_lossy().to_string())
+            .collect();
+        assert_eq!(
+            files,
+            [
+                "pacman-6.0.2-9-x86_64.pkg.tar.zst",
+                "vim-9.1.0000-1-x86_64.pkg.tar.zst",
+                "python-six-1.16.0-8-any.pkg.tar.zst",
+            ]
+        );
+        assert!(rollback.missing.is_empty());
+        assert_eq!(rollback.removed, ["python-six"]);
+
+        // Installing vim is the only change, and installs are left alone.
+        let rollback = transactions[0].rollback(&cache);
+        assert!(rollback.files.is_empty() && rollback.missing.is_empty());
+        assert_eq!(rollback.installed, ["vim"]);
+    }
+}
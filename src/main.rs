@@ -1,7 +1,20 @@
 mod app;
+mod backend;
+mod cache;
 mod commands;
 mod config;
+mod database;
+mod files;
+mod graph;
+mod history;
+mod pkgcache;
+mod query;
+mod runner;
+mod search;
 mod ui;
+mod version;
+mod watcher;
+mod worker;
 
 use std::{error::Error, io::stdout};
 
@@ -18,18 +31,34 @@ use color_eyre::config::HookBuilder;
 use crate::app::*;
 
 fn main() -> Result<(), Box<dyn Error>> {
+    // create app before touching the terminal so any startup error is printed normally
+    let mut app = App::new(parse_backend_arg())?;
+
     // setup terminal
     init_error_hooks()?;
     let terminal = init_terminal()?;
 
-    // create app and run it
-    App::new().run(terminal)?;
+    app.run(terminal)?;
 
     restore_terminal()?;
 
     Ok(())
 }
 
+/// Reads the `--backend <name>` command line option.
+fn parse_backend_arg() -> Option<String> {
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--backend" {
+            return args.next();
+        }
+        if let Some(name) = arg.strip_prefix("--backend=") {
+            return Some(name.to_string());
+        }
+    }
+    None
+}
+
 fn init_error_hooks() -> color_eyre::Result<()> {
     let (panic, error) = HookBuilder::default().into_hooks();
     let panic = panic.into_panic_hook();
@@ -0,0 +1,224 @@
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::version::Version;
+
+/// A package file in one of the cache directories, e.g.
+/// `/var/cache/pacman/pkg/pacman-6.1.0-3-x86_64.pkg.tar.zst`.
+#[derive(Clone)]
+pub struct CachedPackage {
+    pub name: String,
+    pub version: Version,
+    pub path: PathBuf,
+    /// Size of the file in bytes.
+    pub size: u64,
+}
+
+/// Every cached version of a package.
+#[derive(Clone)]
+pub struct CacheGroup {
+    pub name: String,
+    /// Oldest first.
+    pub packages: Vec<CachedPackage>,
+}
+
+impl CacheGroup {
+    /// Size of every file in bytes.
+    pub fn size(&self) -> u64 {
+        self.packages.iter().map(|p| p.size).sum()
+    }
+}
+
+/// Which cached files to remove, like `paccache -r`.
+#[derive(Clone, Copy)]
+pub enum PrunePolicy {
+    /// Everything but the most recent versions of each package, `paccache -rk<n>`.
+    KeepRecent(usize),
+    /// Every version of the packages that aren't installed, `paccache -ruk0`.
+    Uninstalled,
+}
+
+/// Lists every package file in `dirs`, sorted by name and then version, oldest first.
+/// Directories that can't be read and files that aren't packages, like signatures, are skipped.
+pub fn scan(dirs: &[PathBuf]) -> Vec<CachedPackage> {
+    let mut packages: Vec<CachedPackage> = dirs.iter().flat_map(|d| scan_dir(d)).collect();
+    packages.sort_by(|a, b| a.name.cmp(&b.name).then(a.version.cmp(&b.version)));
+    packages
+}
+
+fn scan_dir(dir: &Path) -> Vec<CachedPackage> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return vec![];
+    };
+
+    entries
+        .flatten()
+        .filter_map(|entry| {
+            let file_name = entry.file_name();
+            let (name, version) = parse_file_name(&file_name.to_string_lossy())?;
+            let metadata = entry.metadata().ok().filter(|m| m.is_file())?;
+            Some(CachedPackage {
+                name,
+                version,
+                path: entry.path(),
+                size: metadata.len(),
+            })
+        })
+        .collect()
+}
+
+/// Splits a package file name, `<name>-<pkgver>-<pkgrel>-<arch>.pkg.tar[.<compression>]`, into
+/// its name and version.
+pub fn parse_file_name(file_name: &str) -> Option<(String, Version)> {
+    let (stem, compression) = file_name.split_once(".pkg.tar")?;
+    // Anything after the compression, e.g. `.sig` or `.part`, isn't a usable package.
+    if compression.len() > 1 && compression[1..].contains('.') {
+        return None;
+    }
+
+    let mut parts = stem.rsplitn(4, '-');
+    let _arch = parts.next()?;
+    let pkgrel = parts.next()?;
+    let pkgver = parts.next()?;
+    let name = parts.next().filter(|n| !n.is_empty())?;
+    Some((
+        name.to_string(),
+        Version::from(format!("{}-{}", pkgver, pkgrel).as_str()),
+    ))
+}
+
+/// Groups the output of `scan` by package.
+pub fn group(packages: Vec<CachedPackage>) -> Vec<CacheGroup> {
+    let mut groups: Vec<CacheGroup> = vec![];
+    for package in packages {
+        match groups.last_mut() {
+            Some(group) if group.name == package.name => group.packages.push(package),
+            _ => groups.push(CacheGroup {
+                name: package.name.clone(),
+                packages: vec![package],
+            }),
+        }
+    }
+    groups
+}
+
+/// The files `policy` removes from `groups`. `installed` has the installed version of every
+/// installed package, the installed version is never removed.
+pub fn prune(
+    groups: &[CacheGroup],
+    policy: PrunePolicy,
+    installed: &HashMap<String, Version>,
+) -> Vec<CachedPackage> {
+    let mut removed = vec![];
+    for group in groups {
+        let installed = installed.get(&group.name);
+        let keep = match policy {
+            PrunePolicy::KeepRecent(keep) => keep,
+            PrunePolicy::Uninstalled if installed.is_none() => 0,
+            PrunePolicy::Uninstalled => continue,
+        };
+        let old = group.packages.len().saturating_sub(keep);
+        removed.extend(
+            group.packages[..old]
+                .iter()
+                .filter(|p| installed != Some(&p.version))
+                .cloned(),
+        );
+    }
+    removed
+}
+
+/// The cached file of `name` at exactly `version`, if there is one.
+pub fn find<'a>(
+    packages: &'a [CachedPackage],
+    name: &str,
+    version: &str,
+) -> Option<&'a CachedPackage> {
+    let version = Version::from(version);
+    packages
+        .iter()
+        .find(|p| p.name == name && p.version == version)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_package_file_names() {
+        let (name, version) = parse_file_name("python-six-1.16.0-8-any.pkg.tar.zst").unwrap();
+        assert_eq!(name, "python-six");
+        assert_eq!(version.to_string(), "1.16.0-8");
+
+        let (name, version) = parse_file_name("vim-2:9.1.0000-1-x86_64.pkg.tar.xz").unwrap();
+        assert_eq!(name, "vim");
+        assert_eq!(version.to_string(), "2:9.1.0000-1");
+
+        assert!(parse_file_name("vim-9.1.0000-1-x86_64.pkg.tar.zst.sig").is_none());
+        assert!(parse_file_name("vim-9.1.0000-1-x86_64.pkg.tar.zst.part").is_none());
+        assert!(parse_file_name("x86_64.pkg.tar.zst").is_none());
+    }
+
+    #[test]
+    fn scans_the_cache_oldest_first() {
+        let packages = scan(&[
+            PathBuf::from("tests/fixtures/pkg"),
+            PathBuf::from("tests/fixtures/missing"),
+        ]);
+
+        let files: Vec<String> = packages
+            .iter()
+            .map(|p| p.path.file_name().unwrap().to_string_lossy().to_string())
+            .collect();
+        assert_eq!(
+            files,
+            [
+                "pacman-6.0.2-9-x86_64.pkg.tar.zst",
+                "pacman-6.1.0-3-x86_64.pkg.tar.zst",
+                "python-six-1.16.0-8-any.pkg.tar.zst",
+                "vim-9.0.2190-1-x86_64.pkg.tar.zst",
+                "vim-9.1.0000-1-x86_64.pkg.tar.zst",
+            ]
+        );
+        assert_eq!(
+            find(&packages, "pacman", "6.0.2-9").map(|p| p.size),
+            Some(3)
+        );
+        assert!(find(&packages, "pacman", "6.0.2-8").is_none());
+    }
+
+    #[test]
+    fn prunes_like_paccache() {
+        let groups = group(scan(&[PathBuf::from("tests/fixtures/pkg")]));
+        assert_eq!(groups.len(), 3);
+        assert_eq!(groups[2].size(), 6);
+
+        // The older pacman is installed so it stays, python-six isn't installed at all.
+        let installed = HashMap::from([
+            ("pacman".to_string(), Version::from("6.0.2-9")),
+            ("vim".to_string(), Version::from("9.1.0000-1")),
+        ]);
+        let names = |removed: Vec<CachedPackage>| -> Vec<String> {
+            removed
+                .iter()
+                .map(|p| format!("{} {}", p.name, p.version))
+                .collect()
+        };
+
+        assert_eq!(
+            names(prune(&groups, PrunePolicy::KeepRecent(1), &installed)),
+            ["vim 9.0.2190-1"]
+        );
+        assert_eq!(
+            names(prune(&groups, PrunePolicy::KeepRecent(0), &installed)),
+            ["pacman 6.1.0-3", "python-six 1.16.0-8", "vim 9.0.2190-1"]
+        );
+        assert_eq!(
+            names(prune(&groups, PrunePolicy::Uninstalled, &installed)),
+            ["python-six 1.16.0-8"]
+        );
+    }
+}
@@ -0,0 +1,421 @@
+use crate::commands::{parse_size, PackageType};
+use crate::database::strip_version;
+use crate::search::FuzzySearch;
+
+/// A parsed search, e.g. `python NOT reason:dep OR (desc:wayland AND size:>10M)`.
+///
+/// Bare words fuzzy match the name. Other terms are `field:value`, see `Term`. Terms next to
+/// each other have to all match, `AND`, `OR` and `NOT` combine them otherwise, with `NOT`
+/// binding tightest and `OR` loosest.
+pub enum Query {
+    All,
+    Term(Term),
+    Not(Box<Query>),
+    And(Box<Query>, Box<Query>),
+    Or(Box<Query>, Box<Query>),
+}
+
+pub enum Term {
+    /// A bare word or `name:`.
+    Name(Box<FuzzySearch>, String),
+    /// `desc:`, part of the description. Case insensitive like the other text fields.
+    Description(String),
+    /// `dep:`, depends on a package, ignoring version constraints.
+    Depends(String),
+    /// `provides:`, the package itself or a virtual package it provides.
+    Provides(String),
+    /// `repo:`, the repo the package comes from.
+    Repo(String),
+    /// `license:`, part of one of the licenses.
+    License(String),
+    /// `size:>100M`, compares the installed size. Also takes `<`, `>=`, `<=` and `=`.
+    Size(Comparison, u64),
+    /// `reason:explicit`, `reason:dep`, `reason:orphan` or `reason:foreign`. `reason:dep`
+    /// includes the orphans.
+    Reason(PackageType),
+    /// `file:`, part of the path of one of the package's files.
+    File(String),
+}
+
+#[derive(Clone, Copy)]
+pub enum Comparison {
+    Less,
+    LessOrEqual,
+    Equal,
+    GreaterOrEqual,
+    Greater,
+}
+
+/// What a query is matched against. Anything that isn't known, like the details while they're
+/// still loading, is left empty.
+#[derive(Default)]
+pub struct Target<'a> {
+    pub name: &'a str,
+    pub package_type: Option<&'a PackageType>,
+    pub repo: Option<&'a str>,
+    pub description: &'a str,
+    pub depends: &'a [String],
+    pub provides: &'a [String],
+    pub licenses: &'a [String],
+    pub size: Option<u64>,
+    pub files: &'a [String],
+}
+
+impl Query {
+    pub fn parse(input: &str) -> Result<Query, String> {
+        let tokens = tokenize(input);
+        if tokens.is_empty() {
+            return Ok(Query::All);
+        }
+
+        let mut parser = Parser {
+            tokens,
+            position: 0,
+        };
+        let query = parser.or()?;
+        match parser.peek() {
+            None => Ok(query),
+            Some(token) => Err(format!("Unexpected \"{}\"", token)),
+        }
+    }
+
+    /// How well `target` matches, higher is better. `None` if it doesn't match.
+    /// Only name terms are scored, the others add nothing.
+    pub fn score(&self, target: &Target) -> Option<i64> {
+        match self {
+            Query::All => Some(0),
+            Query::Term(term) => term.score(target),
+            Query::Not(query) => match query.score(target) {
+                Some(_) => None,
+                None => Some(0),
+            },
+            Query::And(a, b) => Some(a.score(target)? + b.score(target)?),
+            Query::Or(a, b) => a.score(target).max(b.score(target)),
+        }
+    }
+
+    /// The first name term that isn't negated, used to highlight the matches in the list.
+    pub fn name_search(&self) -> Option<&FuzzySearch> {
+        match self {
+            Query::Term(Term::Name(search, pattern)) if !pattern.is_empty() => Some(search),
+            Query::And(a, b) | Query::Or(a, b) => a.name_search().or_else(|| b.name_search()),
+            _ => None,
+        }
+    }
+
+    /// Whether any term needs the package details.
+    pub fn needs_details(&self) -> bool {
+        self.any_term(&|t| {
+            matches!(
+                t,
+                Term::Description(_)
+                    | Term::Depends(_)
+                    | Term::Provides(_)
+                    | Term::License(_)
+                    | Term::Size(..)
+            )
+        })
+    }
+
+    pub fn needs_files(&self) -> bool {
+        self.any_term(&|t| matches!(t, Term::File(_)))
+    }
+
+    pub fn needs_repo(&self) -> bool {
+        self.any_term(&|t| matches!(t, Term::Repo(_)))
+    }
+
+    fn any_term(&self, f: &dyn Fn(&Term) -> bool) -> bool {
+        match self {
+            Query::All => false,
+            Query::Term(term) => f(term),
+            Query::Not(query) => query.any_term(f),
+            Query::And(a, b) | Query::Or(a, b) => a.any_term(f) || b.any_term(f),
+        }
+    }
+}
+
+impl Term {
+    fn parse(token: &str) -> Result<Term, String> {
+        let Some((field, value)) = token.split_once(':') else {
+            return Ok(Term::Name(
+                Box::new(FuzzySearch::new(token)),
+                token.to_string(),
+            ));
+        };
+        let text = value.to_lowercase();
+
+        Ok(match field {
+            "name" => Term::Name(Box::new(FuzzySearch::new(value)), value.to_string()),
+            "desc" | "description" => Term::Description(text),
+            "dep" | "depends" => Term::Depends(text),
+            "provides" => Term::Provides(text),
+            "repo" => Term::Repo(text),
+            "license" => Term::License(text),
+            "size" => parse_size_term(value)?,
+            "reason" => Term::Reason(match text.as_str() {
+                "explicit" => PackageType::Explicit,
+                "dep" | "dependency" => PackageType::Dependency,
+                "orphan" => PackageType::Orphan,
+                "foreign" => PackageType::Foreign,
+                _ => return Err(format!("Unknown reason \"{}\"", value)),
+            }),
+            "file" => Term::File(value.to_string()),
+            _ => return Err(format!("Unknown field \"{}\"", field)),
+        })
+    }
+
+    fn score(&self, target: &Target) -> Option<i64> {
+        let contains = |text: &str, part: &str| text.to_lowercase().contains(part);
+        let matched = match self {
+            Term::Name(search, _) => return search.score(target.name),
+            Term::Description(part) => contains(target.description, part),
+            Term::Depends(name) => target
+                .depends
+                .iter()
+                .any(|d| strip_version(d).to_lowercase() == *name),
+            Term::Provides(name) => {
+                target.name.to_lowercase() == *name
+                    || target
+                        .provides
+                        .iter()
+                        .any(|p| strip_version(p).to_lowercase() == *name)
+            }
+            Term::Repo(repo) => target.repo.is_some_and(|r| r.to_lowercase() == *repo),
+            Term::License(part) => target.licenses.iter().any(|l| contains(l, part)),
+            Term::Size(comparison, bytes) => target.size.is_some_and(|size| match comparison {
+                Comparison::Less => size < *bytes,
+                Comparison::LessOrEqual => size <= *bytes,
+                Comparison::Equal => size == *bytes,
+                Comparison::GreaterOrEqual => size >= *bytes,
+                Comparison::Greater => size > *bytes,
+            }),
+            Term::Reason(PackageType::Dependency) => {
+                target.package_type.is_some_and(PackageType::is_dependency)
+            }
+            Term::Reason(package_type) => target.package_type == Some(package_type),
+            Term::File(part) => target.files.iter().any(|f| f.contains(part.as_str())),
+        };
+        matched.then_some(0)
+    }
+}
+
+/// Parses `>100M`, `<=1.5GiB`, `2048` and so on. Units are powers of 1024 like pacman's.
+fn parse_size_term(value: &str) -> Result<Term, String> {
+    let (comparison, amount) = [
+        (">=", Comparison::GreaterOrEqual),
+        ("<=", Comparison::LessOrEqual),
+        (">", Comparison::Greater),
+        ("<", Comparison::Less),
+        ("=", Comparison::Equal),
+    ]
+    .iter()
+    .find_map(|(prefix, c)| value.strip_prefix(prefix).map(|rest| (*c, rest)))
+    .unwrap_or((Comparison::Equal, value));
+
+    let digits = amount
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(amount.len());
+    let (number, unit) = amount.split_at(digits);
+    let unit = match unit.trim().to_uppercase().as_str() {
+        "" | "B" => "B",
+        "K" | "KB" | "KIB" => "KiB",
+        "M" | "MB" | "MIB" => "MiB",
+        "G" | "GB" | "GIB" => "GiB",
+        _ => return Err(format!("Unknown size \"{}\"", value)),
+    };
+    if number.parse::<f64>().is_err() {
+        return Err(format!("Unknown size \"{}\"", value));
+    }
+
+    Ok(Term::Size(
+        comparison,
+        parse_size(&format!("{} {}", number, unit)),
+    ))
+}
+
+/// Splits the input on whitespace, keeping parentheses as their own tokens and anything in
+/// double quotes together, e.g. `desc:"window manager"`.
+fn tokenize(input: &str) -> Vec<String> {
+    let mut tokens = vec![];
+    let mut current = String::new();
+    let mut quoted = false;
+    for c in input.chars() {
+        match c {
+            '"' => quoted = !quoted,
+            c if quoted => current.push(c),
+            '(' | ')' => {
+                if !current.is_empty() {
+                    tokens.push(std::mem::take(&mut current));
+                }
+                tokens.push(c.to_string());
+            }
+            c if c.is_whitespace() => {
+                if !current.is_empty() {
+                    tokens.push(std::mem::take(&mut current));
+                }
+            }
+            c => current.push(c),
+        }
+    }
+    if !current.is_empty() {
+        tokens.push(current);
+    }
+    tokens
+}
+
+struct Parser {
+    tokens: Vec<String>,
+    position: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&str> {
+        self.tokens.get(self.position).map(|t| t.as_str())
+    }
+
+    fn next(&mut self) -> Option<String> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn or(&mut self) -> Result<Query, String> {
+        let mut query = self.and()?;
+        while self.peek() == Some("OR") {
+            self.next();
+            query = Query::Or(Box::new(query), Box::new(self.and()?));
+        }
+        Ok(query)
+    }
+
+    fn and(&mut self) -> Result<Query, String> {
+        let mut query = self.not()?;
+        loop {
+            match self.peek() {
+                Some("AND") => {
+                    self.next();
+                }
+                // Terms next to each other are implicitly ANDed.
+                Some(token) if token != "OR" && token != ")" => {}
+                _ => return Ok(query),
+            }
+            query = Query::And(Box::new(query), Box::new(self.not()?));
+        }
+    }
+
+    fn not(&mut self) -> Result<Query, String> {
+        if self.peek() == Some("NOT") {
+            self.next();
+            return Ok(Query::Not(Box::new(self.not()?)));
+        }
+        self.primary()
+    }
+
+    fn primary(&mut self) -> Result<Query, String> {
+        match self.next().as_deref() {
+            Some("(") => {
+                let query = self.or()?;
+                match self.next().as_deref() {
+                    Some(")") => Ok(query),
+                    _ => Err("Missing \")\"".to_string()),
+                }
+            }
+            Some(")") => Err("Unexpected \")\"".to_string()),
+            Some(token @ ("AND" | "OR" | "NOT")) => Err(format!("Missing a term before {}", token)),
+            Some(token) => Ok(Query::Term(Term::parse(token)?)),
+            None => Err("Missing a term at the end".to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(query: &str, target: &Target) -> bool {
+        Query::parse(query).unwrap().score(target).is_some()
+    }
+
+    fn python_pip() -> (Vec<String>, Vec<String>, Vec<String>) {
+        (
+            vec!["python>=3.12".to_string(), "python-setuptools".to_string()],
+            vec!["python-pip=24.0".to_string()],
+            vec!["MIT".to_string()],
+        )
+    }
+
+    #[test]
+    fn matches_fields() {
+        let (depends, provides, licenses) = python_pip();
+        let target = Target {
+            name: "python-pip",
+            package_type: Some(&PackageType::Dependency),
+            repo: Some("extra"),
+            description: "The PyPA recommended tool for installing Python packages",
+            depends: &depends,
+            provides: &provides,
+            licenses: &licenses,
+            size: Some(5 * 1024 * 1024),
+            files: &["/usr/bin/pip".to_string()],
+        };
+
+        assert!(matches("pip", &target));
+        assert!(matches("desc:pypa", &target));
+        assert!(matches("dep:python", &target));
+        assert!(!matches("dep:pyth", &target));
+        assert!(matches("provides:python-pip", &target));
+        assert!(matches("repo:extra", &target));
+        assert!(matches("license:mit", &target));
+        assert!(matches("size:>4M", &target));
+        assert!(!matches("size:<=4.5MiB", &target));
+        assert!(matches("reason:dep", &target));
+        assert!(matches("file:bin/pip", &target));
+        assert!(matches("desc:\"installing python\"", &target));
+    }
+
+    #[test]
+    fn combines_terms() {
+        let target = Target {
+            name: "wayland",
+            package_type: Some(&PackageType::Explicit),
+            ..Default::default()
+        };
+
+        assert!(matches("", &target));
+        assert!(matches("way reason:explicit", &target));
+        assert!(!matches("way AND reason:dep", &target));
+        assert!(matches("reason:dep OR way", &target));
+        assert!(matches("NOT reason:dep", &target));
+        assert!(!matches("NOT way OR reason:orphan", &target));
+        assert!(matches("x11 OR (way NOT reason:foreign)", &target));
+    }
+
+    #[test]
+    fn counts_orphans_as_dependencies() {
+        let target = Target {
+            name: "libfoo",
+            package_type: Some(&PackageType::Orphan),
+            ..Default::default()
+        };
+
+        assert!(matches("reason:dep", &target));
+        assert!(matches("reason:orphan", &target));
+        assert!(!matches("reason:explicit", &target));
+    }
+
+    #[test]
+    fn rejects_broken_queries() {
+        for query in [
+            "foo:bar",
+            "size:>lots",
+            "reason:whim",
+            "(way",
+            "way)",
+            "way OR",
+            "AND",
+        ] {
+            assert!(Query::parse(query).is_err(), "{}", query);
+        }
+    }
+}
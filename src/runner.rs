@@ -0,0 +1,83 @@
+use std::{
+    io::{self, stdout, BufRead, BufReader, Read},
+    process::{Command, Stdio},
+    sync::mpsc::{self, Receiver, Sender},
+    thread,
+};
+
+use crossterm::{
+    terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
+    ExecutableCommand,
+};
+
+pub enum CommandOutput {
+    Line(String),
+    /// The command exited, `true` if it was successful.
+    Finished(bool),
+}
+
+/// Spawns `command` in the background. Its stdout and stderr are sent line by line through the
+/// returned channel, followed by `CommandOutput::Finished` once it exits.
+pub fn spawn(command: &[String]) -> io::Result<Receiver<CommandOutput>> {
+    let mut child = Command::new(&command[0])
+        .args(&command[1..])
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+
+    let (sender, receiver) = mpsc::channel();
+
+    let stderr = child.stderr.take().unwrap();
+    let stderr_sender = sender.clone();
+    let stderr_thread = thread::spawn(move || send_lines(stderr, &stderr_sender));
+
+    let stdout = child.stdout.take().unwrap();
+    thread::spawn(move || {
+        send_lines(stdout, &sender);
+        let _ = stderr_thread.join();
+
+        let success = child.wait().map(|s| s.success()).unwrap_or(false);
+        let _ = sender.send(CommandOutput::Finished(success));
+    });
+
+    Ok(receiver)
+}
+
+fn send_lines(output: impl Read, sender: &Sender<CommandOutput>) {
+    for line in BufReader::new(output).lines().map_while(Result::ok) {
+        if sender.send(CommandOutput::Line(line)).is_err() {
+            break;
+        }
+    }
+}
+
+/// Whether `privilege_command` can run something without asking for a password, i.e. it
+/// remembers the last time it was given one. Checked with `-n`, which sudo and doas both take.
+pub fn is_authenticated(privilege_command: &str) -> bool {
+    Command::new(privilege_command)
+        .args(["-n", "true"])
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .is_ok_and(|s| s.success())
+}
+
+/// Leaves the TUI to let `privilege_command` (sudo, doas, ...) ask for a password, so the real
+/// command can then run in the background without needing the terminal. That only works if the
+/// password is remembered afterwards, see `is_authenticated`.
+///
+/// Returns `false` if authentication failed. The caller needs to redraw the whole terminal after.
+pub fn authenticate(privilege_command: &str, command: &[String]) -> io::Result<bool> {
+    disable_raw_mode()?;
+    stdout().execute(LeaveAlternateScreen)?;
+
+    println!("Authenticating to run: {}", command.join(" "));
+    let status = Command::new(privilege_command).arg("true").status();
+
+    stdout().execute(EnterAlternateScreen)?;
+    enable_raw_mode()?;
+
+    Ok(status.map(|s| s.success()).unwrap_or(false))
+}
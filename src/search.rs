@@ -0,0 +1,55 @@
+use fuzzy_matcher::{skim::SkimMatcherV2, FuzzyMatcher};
+use ratatui::{style::Style, text::Span};
+
+/// fzf style fuzzy matching, e.g. `lbcrl` matches `libcurl`. Case insensitive unless the
+/// pattern has an uppercase letter.
+pub struct FuzzySearch {
+    pattern: String,
+    matcher: SkimMatcherV2,
+}
+
+impl FuzzySearch {
+    pub fn new(pattern: &str) -> Self {
+        Self {
+            pattern: pattern.to_string(),
+            matcher: SkimMatcherV2::default().smart_case(),
+        }
+    }
+
+    /// How well `text` matches, higher is better. `None` if it doesn't match at all.
+    pub fn score(&self, text: &str) -> Option<i64> {
+        if self.pattern.is_empty() {
+            return Some(0);
+        }
+        self.matcher.fuzzy_match(text, &self.pattern)
+    }
+
+    /// Splits `text` into spans, styling the characters matching the pattern with `matched`.
+    pub fn highlight(&self, text: &str, style: Style, matched: Style) -> Vec<Span<'static>> {
+        let indices = self
+            .matcher
+            .fuzzy_indices(text, &self.pattern)
+            .map(|(_, indices)| indices)
+            .unwrap_or_default();
+
+        let mut spans: Vec<Span> = vec![];
+        let mut current = String::new();
+        let mut current_matched = false;
+        for (i, c) in text.chars().enumerate() {
+            let is_matched = indices.contains(&i);
+            if is_matched != current_matched && !current.is_empty() {
+                let style = if current_matched { matched } else { style };
+                spans.push(Span::styled(std::mem::take(&mut current), style));
+            }
+            current_matched = is_matched;
+            current.push(c);
+        }
+        if !current.is_empty() {
+            spans.push(Span::styled(
+                current,
+                if current_matched { matched } else { style },
+            ));
+        }
+        spans
+    }
+}
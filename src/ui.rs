@@ -1,20 +1,60 @@
+use std::collections::HashMap;
+use std::io::{self, stdout, Write};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
 use ratatui::{layout::*, style::*, text::*, widgets::*, Frame};
 
-use crate::{app::App, commands::PackageType};
+use crate::{
+    app::{search_queries, App, ListFilter, SortOrder},
+    commands::{
+        format_date, format_size, format_size_change, PackageDetails, PackageType,
+        PackageVersionInfo,
+    },
+    config::Column,
+    history::{EventKind, HistoryEvent, PastTransaction},
+    query::Query,
+    search::FuzzySearch,
+};
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub enum MenuItem {
     PackageList,
+    SyncList,
+    Updates,
+    History,
+    Cache,
 }
 
 impl From<MenuItem> for usize {
     fn from(input: MenuItem) -> usize {
         match input {
             MenuItem::PackageList => 0,
+            MenuItem::SyncList => 1,
+            MenuItem::Updates => 2,
+            MenuItem::History => 3,
+            MenuItem::Cache => 4,
         }
     }
 }
 
+impl MenuItem {
+    const ALL: [MenuItem; 5] = [
+        MenuItem::PackageList,
+        MenuItem::SyncList,
+        MenuItem::Updates,
+        MenuItem::History,
+        MenuItem::Cache,
+    ];
+
+    pub fn next(self) -> MenuItem {
+        Self::ALL[(usize::from(self) + 1) % Self::ALL.len()]
+    }
+
+    pub fn previous(self) -> MenuItem {
+        Self::ALL[(usize::from(self) + Self::ALL.len() - 1) % Self::ALL.len()]
+    }
+}
+
 pub fn create_menu<'a>(menu_titles: &Vec<&'a str>) -> Vec<Line<'a>> {
     menu_titles
         .iter()
@@ -49,49 +89,702 @@ pub fn render_tabs<'a>(
     frame.render_widget(tabs, chunk);
 }
 
-pub fn render_footer<'a>(frame: &mut Frame<'_>, chunk: Rect) {
-    let footer = Paragraph::new("\nUse ↓/j and ↑/k to move, g/G to go top/bottom. e to show explicitly installed packages, o to show orphan packages, f to show foreign packages (AUR/manual install), s to search, a to reset the filter").centered();
+pub fn render_footer(frame: &mut Frame<'_>, chunk: Rect, message: Option<&str>) {
+    let text = message.unwrap_or("Use ↓/j and ↑/k to move, s to search, space to mark packages, Tab to switch tabs, ? for all keys");
+    let footer = Paragraph::new("\n".to_owned() + text).centered();
     frame.render_widget(footer, chunk);
 }
 
+const GLOBAL_HELP: &[(&str, &str)] = &[
+    ("Tab", "switch tabs, or Shift + the underlined letter"),
+    ("?", "show this help"),
+    (
+        "r",
+        "reload the packages, this happens by itself when they change",
+    ),
+    ("W", "find the package owning a path, like pacman -Qo"),
+    ("q", "quit"),
+];
+
+const PACKAGE_LIST_HELP: &[(&str, &str)] = &[
+    ("↓/j ↑/k", "move"),
+    ("g/G", "go top/bottom"),
+    (
+        "",
+        "Filters stack, e.g. f then s python shows foreign packages matching python",
+    ),
+    ("a", "clear the filters"),
+    ("Backspace", "remove the last filter"),
+    (
+        "1-9",
+        "remove that filter, they're numbered in the list title",
+    ),
+    ("e", "only explicitly installed packages, again to remove"),
+    (
+        "n",
+        "only packages installed as dependencies, orphans included",
+    ),
+    ("o", "only orphan packages"),
+    ("f", "only foreign packages (AUR/manual install)"),
+    ("b", "only packages built from version control (-git etc.)"),
+    ("x", "only packages with an epoch in their version"),
+    ("s", "search, Tab in the search to toggle ranking"),
+    (
+        "",
+        "Words fuzzy match the name, other fields are desc: dep: provides: repo:",
+    ),
+    (
+        "",
+        "license: file: size:>100M reason:explicit/dep/orphan/foreign",
+    ),
+    ("", "Combine them with AND, OR, NOT and parentheses"),
+    ("i", "show/hide versions in the list"),
+    ("L", "switch between the list and the table view"),
+    ("|", "choose the table's columns and their widths"),
+    ("</>", "make the package list narrower/wider"),
+    (
+        "z",
+        "zoom the package list to the whole width, again to go back",
+    ),
+    ("O", "choose the sort order"),
+    ("R", "reverse the sort order"),
+    ("space", "mark/unmark the selected package"),
+    ("v", "invert the marks of the shown packages"),
+    ("V", "mark all shown packages"),
+    ("u", "unmark everything"),
+    (
+        "",
+        "The actions below apply to the marked packages, or the selected one",
+    ),
+    ("d", "remove"),
+    ("E", "mark as explicitly installed"),
+    ("D", "mark as dependency"),
+    ("y", "copy the names"),
+    (
+        "w",
+        "export the names to a file in ~/.local/share/pacmanman",
+    ),
+    ("c", "clean up orphans"),
+    ("t", "dependency tree of the selected package"),
+    ("T", "tree of the packages requiring the selected package"),
+    ("B", "downgrade the selected package from the package cache"),
+    ("l", "files of the selected package"),
+];
+
+const SYNC_LIST_HELP: &[(&str, &str)] = &[
+    ("↓/j ↑/k", "move"),
+    ("g/G", "go top/bottom"),
+    (
+        "s",
+        "search like on the packages tab, Tab in the search to toggle ranking",
+    ),
+    ("a", "clear the searches"),
+    ("space", "mark/unmark the selected package"),
+    ("u", "unmark everything"),
+    ("i", "install the marked packages, or the selected one"),
+];
+
+const UPDATES_HELP: &[(&str, &str)] = &[
+    ("↓/j ↑/k", "move"),
+    ("g/G", "go top/bottom"),
+    ("space", "mark/unmark the selected package"),
+    ("u", "unmark everything"),
+    ("i", "upgrade the marked packages, or the selected one"),
+    ("A", "sync the databases and upgrade everything"),
+];
+
+const HISTORY_HELP: &[(&str, &str)] = &[
+    ("↓/j ↑/k", "move"),
+    ("g/G", "go top/bottom"),
+    ("f/F", "cycle through the kinds of events shown"),
+    ("s", "search the events, e.g. a package or hook name"),
+    ("a", "show everything again"),
+    ("Enter", "go to the event's package in the package list"),
+    ("t", "switch between the events and the transactions"),
+    (
+        "u",
+        "roll back the selected transaction with the package cache",
+    ),
+];
+
+const CACHE_HELP: &[(&str, &str)] = &[
+    ("↓/j ↑/k", "move"),
+    ("g/G", "go top/bottom"),
+    (
+        "+/-",
+        "keep more/fewer versions of each package when pruning",
+    ),
+    ("x", "remove all but the kept versions of each package"),
+    (
+        "X",
+        "remove every version of the packages that aren't installed",
+    ),
+    (
+        "",
+        "Both show the files to remove first, installed versions are never removed",
+    ),
+];
+
+pub fn render_help_popup(frame: &mut Frame<'_>, active_menu_item: MenuItem) {
+    let block = Block::default()
+        .title("Keys (Esc to close)")
+        .borders(Borders::ALL);
+    let area = centered_rect(60, 90, frame.size());
+
+    let help = match active_menu_item {
+        MenuItem::PackageList => PACKAGE_LIST_HELP,
+        MenuItem::SyncList => SYNC_LIST_HELP,
+        MenuItem::Updates => UPDATES_HELP,
+        MenuItem::History => HISTORY_HELP,
+        MenuItem::Cache => CACHE_HELP,
+    };
+
+    let lines: Vec<Line> = help
+        .iter()
+        .chain(GLOBAL_HELP)
+        .map(|(key, description)| {
+            Line::from(vec![
+                Span::styled(format!("{:>10}  ", key), Style::default().fg(Color::Yellow)),
+                Span::raw(*description),
+            ])
+        })
+        .collect();
+
+    frame.render_widget(Clear, area);
+    frame.render_widget(Paragraph::new(lines).block(block), area);
+}
+
 impl App {
-    pub fn render_package_details<'a>(&mut self, frame: &mut Frame<'_>, chunk: Rect) {
+    pub fn render_package_details(&mut self, frame: &mut Frame<'_>, chunk: Rect) {
+        let width = match (self.zoomed, self.config.table_view) {
+            (true, _) => 100,
+            (false, true) => self.config.table_width,
+            (false, false) => self.config.list_width,
+        };
         let layout = Layout::default()
             .direction(Direction::Horizontal)
-            .constraints([Constraint::Percentage(10), Constraint::Percentage(90)].as_ref())
+            .constraints(
+                [
+                    Constraint::Percentage(width),
+                    Constraint::Percentage(100 - width),
+                ]
+                .as_ref(),
+            )
             .split(chunk);
 
         let block = Block::default()
             .borders(Borders::ALL)
             .style(Style::default().fg(Color::White))
-            .title("Packages")
+            .title(filters_title(
+                &format!("Packages {} {}", self.sort_arrow(), self.sort_order.label()),
+                &self.packages_list.filters,
+            ))
             .border_type(BorderType::Plain);
+        let highlight_style = Style::default()
+            .fg(self.config.theme.selected_fg)
+            .bg(self.config.theme.selected_bg)
+            .add_modifier(Modifier::BOLD)
+            .add_modifier(Modifier::REVERSED);
 
+        if self.config.table_view {
+            self.render_package_table(frame, layout[0], block, highlight_style);
+        } else {
+            self.render_package_list(frame, layout[0], block, highlight_style);
+        }
+        if self.zoomed {
+            return;
+        }
+
+        let index = self.packages_list.state.selected().unwrap_or_default();
+
+        let selected_package = self.packages_list.filtered_items[index].clone();
+
+        let details_block = Block::default()
+            .borders(Borders::ALL)
+            .style(Style::default().fg(Color::White))
+            .title(selected_package.name.clone() + " Details")
+            .border_type(BorderType::Plain);
+
+        let details_text = match self.details_cache.get(&selected_package.name).cloned() {
+            Some(details) => self.details_lines(&selected_package, details),
+            None => vec![Line::raw("Loading details...")],
+        };
+
+        let details_display = Paragraph::new(details_text)
+            .block(details_block)
+            .wrap(Wrap { trim: false });
+
+        frame.render_widget(details_display, layout[1]);
+    }
+
+    fn render_package_list(
+        &mut self,
+        frame: &mut Frame<'_>,
+        chunk: Rect,
+        block: Block<'_>,
+        highlight_style: Style,
+    ) {
+        let queries = search_queries(&self.packages_list.filters);
+        let search = queries.iter().find_map(Query::name_search);
         let items: Vec<_> = self
             .packages_list
             .filtered_items
             .iter()
             .map(|p| {
-                let style = match p.package_type {
-                    PackageType::Explicit => Style::default(),
-                    PackageType::Orphan => Style::default()
-                        .fg(self.config.theme.orphan_fg)
-                        .bg(self.config.theme.orphan_bg),
-                    PackageType::Foreign => Style::default()
-                        .fg(self.config.theme.foreign_fg)
-                        .bg(self.config.theme.foreign_bg),
-                };
+                let mut spans = vec![self.mark_span(&p.name)];
+                spans.extend(highlight_name(search, &p.name, self.package_style(p)));
+                if self.config.show_version {
+                    spans.push(Span::styled(
+                        " ".to_owned() + &p.version.to_string(),
+                        Style::default().fg(Color::Green),
+                    ));
+                }
 
-                ListItem::new(Line::from(vec![Span::styled(p.name.clone(), style)]))
+                ListItem::new(Line::from(spans))
             })
             .collect();
 
-        let index = match self.packages_list.state.selected() {
-            Some(i) => i,
-            None => 0,
+        let list = List::new(items)
+            .block(block)
+            .highlight_style(highlight_style)
+            .highlight_symbol(">")
+            .highlight_spacing(HighlightSpacing::Always);
+
+        frame.render_stateful_widget(list, chunk, &mut self.packages_list.state);
+    }
+
+    fn render_package_table(
+        &mut self,
+        frame: &mut Frame<'_>,
+        chunk: Rect,
+        block: Block<'_>,
+        highlight_style: Style,
+    ) {
+        let queries = search_queries(&self.packages_list.filters);
+        let search = queries.iter().find_map(Query::name_search);
+        let columns = self.shown_columns();
+        let repos: HashMap<&str, &str> = if columns.contains(&Column::Repo) {
+            self.sync_list
+                .items
+                .iter()
+                .map(|p| (p.name.as_str(), p.repo.as_str()))
+                .collect()
+        } else {
+            HashMap::new()
         };
 
-        let mut selected_package = self.packages_list.filtered_items[index].clone();
+        let rows: Vec<_> = self
+            .packages_list
+            .filtered_items
+            .iter()
+            .map(|p| {
+                let details = self.details_cache.get(&p.name);
+                let style = self.package_style(p);
+                let mut cells = vec![Cell::from(self.mark_span(&p.name))];
+                cells.extend(columns.iter().map(|column| {
+                    match column {
+                        Column::Name => {
+                            Cell::from(Line::from(highlight_name(search, &p.name, style)))
+                        }
+                        Column::Version => Cell::from(p.version.to_string()),
+                        Column::Type => Cell::from(match p.package_type {
+                            PackageType::Explicit => "explicit",
+                            PackageType::Dependency => "dependency",
+                            PackageType::Orphan => "orphan",
+                            PackageType::Foreign => "foreign",
+                        }),
+                        Column::Size => Cell::from(
+                            details
+                                .map(|d| format_size(d.installed_bytes))
+                                .unwrap_or_default(),
+                        ),
+                        Column::Repo => {
+                            Cell::from(repos.get(p.name.as_str()).copied().unwrap_or_default())
+                        }
+                        Column::InstallDate => Cell::from(
+                            details
+                                .and_then(|d| d.install_date)
+                                .map(format_date)
+                                .unwrap_or_default(),
+                        ),
+                    }
+                }));
+                Row::new(cells)
+            })
+            .collect();
+
+        let mut widths = vec![Constraint::Length(2)];
+        widths.extend(
+            self.config
+                .columns
+                .iter()
+                .filter(|c| c.shown)
+                .map(|c| Constraint::Length(c.width)),
+        );
+        let mut header = vec![Cell::from("")];
+        header.extend(columns.iter().map(|c| Cell::from(c.title())));
+
+        let table = Table::new(rows, widths)
+            .header(Row::new(header).style(Style::default().add_modifier(Modifier::BOLD)))
+            .block(block)
+            .highlight_style(highlight_style)
+            .highlight_symbol(">")
+            .highlight_spacing(HighlightSpacing::Always);
+
+        // The table shares its selection and scroll position with the list view.
+        let list_state = &mut self.packages_list.state;
+        let mut state = TableState::default()
+            .with_selected(list_state.selected())
+            .with_offset(list_state.offset());
+        frame.render_stateful_widget(table, chunk, &mut state);
+        *list_state.offset_mut() = state.offset();
+    }
+
+    fn package_style(&self, package: &PackageVersionInfo) -> Style {
+        let theme = &self.config.theme;
+        match package.package_type {
+            PackageType::Explicit => Style::default().fg(theme.fg).bg(theme.bg),
+            PackageType::Dependency => Style::default()
+                .fg(theme.fg)
+                .bg(theme.bg)
+                .add_modifier(Modifier::DIM),
+            PackageType::Orphan => Style::default().fg(theme.orphan_fg).bg(theme.orphan_bg),
+            PackageType::Foreign => Style::default().fg(theme.foreign_fg).bg(theme.foreign_bg),
+        }
+    }
+
+    fn mark_span(&self, name: &str) -> Span<'static> {
+        let marker = if self.packages_list.marked.contains(name) {
+            "● "
+        } else {
+            "  "
+        };
+        Span::styled(marker, Style::default().fg(Color::Yellow))
+    }
+
+    pub fn render_column_menu(&mut self, frame: &mut Frame<'_>) {
+        let area = centered_rect(50, 40, frame.size());
+        let block = Block::default()
+            .title("Columns (space show/hide, J/K move, h/l width, Esc to close)")
+            .borders(Borders::ALL);
+
+        let items: Vec<_> = self
+            .config
+            .columns
+            .iter()
+            .map(|c| {
+                let tick = if c.shown { "[x]" } else { "[ ]" };
+                ListItem::new(format!("{} {:<12} {:>3}", tick, c.column.title(), c.width))
+            })
+            .collect();
+
+        let list = List::new(items).block(block).highlight_style(
+            Style::default()
+                .fg(self.config.theme.selected_fg)
+                .bg(self.config.theme.selected_bg),
+        );
+
+        frame.render_widget(Clear, area);
+        frame.render_stateful_widget(list, area, &mut self.column_menu);
+    }
+
+    pub fn render_file_list(&mut self, frame: &mut Frame<'_>) {
+        let area = centered_rect(70, 80, frame.size());
+        let Some(list) = self.file_list.as_mut() else {
+            return;
+        };
+        let title = format!("Files of {} (Esc to close)", list.package);
+        if list.loading {
+            let para = Paragraph::new("Loading files...")
+                .block(Block::default().title(title).borders(Borders::ALL));
+            frame.render_widget(Clear, area);
+            frame.render_widget(para, area);
+            return;
+        }
+
+        let size: u64 = list.rows.iter().filter_map(|r| r.size).sum();
+        let block = Block::default()
+            .title(format!(
+                "{} - {} files, {}",
+                title,
+                list.rows.iter().filter(|r| !r.is_dir()).count(),
+                format_size(size)
+            ))
+            .borders(Borders::ALL);
+
+        let items: Vec<_> = list
+            .rows
+            .iter()
+            .map(|row| {
+                let name = format!("{}{}", "  ".repeat(row.depth), row.name());
+                let mut spans = vec![
+                    Span::raw(format!(
+                        "{:>11}  ",
+                        row.size.map(format_size).unwrap_or_default()
+                    )),
+                    if row.is_dir() {
+                        Span::styled(name, Style::default().fg(Color::Blue))
+                    } else {
+                        Span::raw(name)
+                    },
+                ];
+                if row.backup {
+                    spans.push(Span::styled(
+                        " (backup)",
+                        Style::default().fg(Color::Yellow),
+                    ));
+                }
+                if row.missing {
+                    spans.push(Span::styled(" (missing)", Style::default().fg(Color::Red)));
+                }
+                ListItem::new(Line::from(spans))
+            })
+            .collect();
+
+        let list_widget = List::new(items).block(block).highlight_style(
+            Style::default()
+                .fg(self.config.theme.selected_fg)
+                .bg(self.config.theme.selected_bg),
+        );
+
+        frame.render_widget(Clear, area);
+        frame.render_stateful_widget(list_widget, area, &mut list.state);
+    }
+
+    pub fn render_owner_popup(&mut self, frame: &mut Frame<'_>) {
+        let block = Block::default()
+            .title("Who owns this path? (a path or a command, Enter to go to the package)")
+            .borders(Borders::ALL);
+        let area = centered_rect(60, 20, frame.size());
+        let input = Paragraph::new(self.owner_input.value()).block(block);
+
+        let width = area.width.max(3) - 3;
+        let scroll = self.owner_input.visual_scroll(width as usize);
+        frame.set_cursor(
+            area.x + (self.owner_input.visual_cursor().max(scroll) - scroll) as u16 + 1,
+            area.y + 1,
+        );
+
+        frame.render_widget(Clear, area);
+        frame.render_widget(input, area);
+    }
+
+    pub fn render_downgrade(&mut self, frame: &mut Frame<'_>) {
+        let Some(downgrade) = self.downgrade.as_mut() else {
+            return;
+        };
+        let area = centered_rect(60, 50, frame.size());
+        let ignore = if downgrade.ignore { "[x]" } else { "[ ]" };
+        let block = Block::default()
+            .title(format!(
+                "Downgrade {} - {} i: add to IgnorePkg after (Enter to install, Esc to cancel)",
+                downgrade.name, ignore
+            ))
+            .borders(Borders::ALL);
+
+        let items: Vec<_> = downgrade
+            .packages
+            .iter()
+            .map(|p| {
+                let mut spans = vec![
+                    Span::raw(format!("{:<24}", p.version.to_string())),
+                    Span::raw(format!("{:>11}  ", format_size(p.size))),
+                    Span::raw(p.path.to_string_lossy().to_string()),
+                ];
+                if p.version == downgrade.installed {
+                    spans.push(Span::styled(
+                        " (installed)",
+                        Style::default().fg(Color::Green),
+                    ));
+                }
+                ListItem::new(Line::from(spans))
+            })
+            .collect();
+
+        let list = List::new(items).block(block).highlight_style(
+            Style::default()
+                .fg(self.config.theme.selected_fg)
+                .bg(self.config.theme.selected_bg),
+        );
+
+        frame.render_widget(Clear, area);
+        frame.render_stateful_widget(list, area, &mut downgrade.state);
+    }
+
+    fn details_lines(
+        &mut self,
+        package: &PackageVersionInfo,
+        details: PackageDetails,
+    ) -> Vec<Line<'static>> {
+        let date = |d: Option<i64>| d.map(format_date).unwrap_or_default();
+        let mut details_text: Vec<Line> = [
+            ("Version", details.version),
+            ("Description", details.description),
+            ("Architecture", details.architecture),
+            ("URL", details.url),
+            ("Licenses", join_vec(details.licenses)),
+            ("Groups", join_vec(details.groups)),
+            ("Provides", join_vec(details.provides)),
+            ("Depends On", join_vec(details.depends_on)),
+            (
+                "Optional dependencies",
+                join_vec(details.optional_dependencies),
+            ),
+            ("Required by", join_vec(details.required_by)),
+            ("Optional for", join_vec(details.optional_for)),
+            ("Conflicts with", join_vec(details.conflicts)),
+            ("Replaces", join_vec(details.replaces)),
+            ("Installed size", details.installed_size),
+            ("Packager", details.packager),
+            ("Build date", date(details.build_date)),
+            ("Install date", date(details.install_date)),
+            ("Install reason", details.installed_reason),
+            ("Validated by", join_vec(details.validated_by)),
+            ("Backup files", join_vec(details.backup_files)),
+        ]
+        .into_iter()
+        .map(|(label, value)| Line::styled(label.to_owned() + ": " + &value, Style::default()))
+        .collect();
+
+        if matches!(
+            package.package_type,
+            PackageType::Dependency | PackageType::Orphan
+        ) {
+            details_text.push(Line::raw(""));
+            details_text.extend(self.why_installed_lines(&package.name));
+        }
+        details_text.push(Line::raw(""));
+        details_text.extend(self.package_history_lines(&package.name));
+
+        details_text
+    }
+
+    /// What happened to `name` according to the log, oldest first.
+    fn package_history_lines(&self, name: &str) -> Vec<Line<'static>> {
+        const MAX_EVENTS: usize = 20;
+
+        let mut lines = vec![Line::styled(
+            "History",
+            Style::default().add_modifier(Modifier::BOLD),
+        )];
+        if self.loading_history && self.history_list.items.is_empty() {
+            lines.push(Line::raw("  Loading the history..."));
+            return lines;
+        }
+
+        // The list is newest first.
+        let events: Vec<&HistoryEvent> = self
+            .history_list
+            .items
+            .iter()
+            .filter(|e| e.package() == Some(name))
+            .take(MAX_EVENTS)
+            .collect();
+        if events.is_empty() {
+            lines.push(Line::raw("  Nothing in the log."));
+        }
+        for event in events.into_iter().rev() {
+            let mut line = history_line(event);
+            line.spans.insert(0, Span::raw("  "));
+            lines.push(line);
+        }
+        lines
+    }
+
+    /// Explains which explicitly installed packages pull in the dependency `name`, e.g.
+    /// `libfoo ← bar ← baz (explicit)`.
+    fn why_installed_lines(&mut self, name: &str) -> Vec<Line<'static>> {
+        const MAX_CHAINS: usize = 10;
+
+        let mut lines = vec![Line::styled(
+            "Why is this installed?",
+            Style::default().add_modifier(Modifier::BOLD),
+        )];
+        let Some(graph) = self.dependency_graph() else {
+            lines.push(Line::raw("  Working out the dependencies..."));
+            return lines;
+        };
+        let chains = graph.why_installed(name);
+        if chains.is_empty() {
+            lines.push(Line::raw(if graph.links(name, true).is_empty() {
+                "  Nothing installed needs it, it can be removed as an orphan."
+            } else {
+                "  Only needed by dependencies no explicitly installed package needs, e.g. a cycle."
+            }));
+            return lines;
+        }
+
+        for chain in chains.iter().take(MAX_CHAINS) {
+            let mut spans = vec![Span::raw("  ".to_owned() + name)];
+            for link in chain {
+                spans.push(Span::styled(
+                    if link.optional {
+                        " ←(optional) "
+                    } else {
+                        " ← "
+                    },
+                    Style::default().add_modifier(Modifier::DIM),
+                ));
+                spans.push(Span::raw(link.name.clone()));
+            }
+            spans.push(Span::styled(
+                " (explicit)",
+                Style::default().fg(Color::Green),
+            ));
+            lines.push(Line::from(spans));
+        }
+        if chains.len() > MAX_CHAINS {
+            lines.push(Line::raw(format!(
+                "  ...and {} more",
+                chains.len() - MAX_CHAINS
+            )));
+        }
+        lines
+    }
+
+    pub fn render_sync_details(&mut self, frame: &mut Frame<'_>, chunk: Rect) {
+        let layout = Layout::default()
+            .direction(Direction::Horizontal)
+            .constraints([Constraint::Percentage(20), Constraint::Percentage(80)].as_ref())
+            .split(chunk);
+
+        let block = Block::default()
+            .borders(Borders::ALL)
+            .style(Style::default().fg(Color::White))
+            .title(filters_title("Sync packages", &self.sync_list.filters))
+            .border_type(BorderType::Plain);
+
+        let queries = search_queries(&self.sync_list.filters);
+        let search = queries.iter().find_map(Query::name_search);
+        let items: Vec<_> = self
+            .sync_list
+            .filtered_items
+            .iter()
+            .map(|p| {
+                let name = format!("{}/{}", p.repo, p.name);
+                let marker = if self.sync_list.marked.contains(&name) {
+                    "● "
+                } else {
+                    "  "
+                };
+                // Flag what is already installed.
+                let installed = if p.installed { " [installed]" } else { "" };
+
+                let style = Style::default().fg(self.config.theme.fg);
+                let mut spans = vec![
+                    Span::styled(marker, Style::default().fg(Color::Yellow)),
+                    Span::styled(p.repo.clone() + "/", style),
+                ];
+                spans.extend(highlight_name(search, &p.name, style));
+                spans.push(Span::styled(
+                    " ".to_owned() + &p.version.to_string(),
+                    Style::default().fg(Color::Green),
+                ));
+                spans.push(Span::styled(installed, Style::default().fg(Color::Cyan)));
+
+                ListItem::new(Line::from(spans))
+            })
+            .collect();
 
         let list = List::new(items)
             .block(block)
@@ -105,60 +798,157 @@ impl App {
             .highlight_symbol(">")
             .highlight_spacing(HighlightSpacing::Always);
 
-        let package_details = selected_package.get_details();
+        let package = self.sync_list.selected().cloned().unwrap_or_default();
         let details_block = Block::default()
             .borders(Borders::ALL)
             .style(Style::default().fg(Color::White))
-            .title(package_details.name + " Details")
+            .title(package.name.clone() + " Details")
             .border_type(BorderType::Plain);
 
         let details_text = vec![
-            Line::styled(
-                "Version: ".to_owned() + &package_details.version.clone(),
-                Style::default(),
-            ),
-            Line::styled(
-                "Description: ".to_owned() + &package_details.description.clone(),
-                Style::default(),
-            ),
-            Line::styled(
-                "Depends On: ".to_owned() + &join_vec(package_details.depends_on.to_owned()),
-                Style::default(),
-            ),
-            Line::styled(
-                "Optional dependencies: ".to_owned()
-                    + &join_vec(package_details.optional_dependencies).to_owned(),
-                Style::default(),
-            ),
-            Line::styled(
-                "Optional for: ".to_owned() + &join_vec(package_details.optional_for).to_owned(),
-                Style::default(),
-            ),
-            Line::styled(
-                "Installed size: ".to_owned() + &package_details.installed_size.clone(),
-                Style::default(),
-            ),
-            Line::styled(
-                "Install reason: ".to_owned() + &package_details.installed_reason.clone(),
-                Style::default(),
-            ),
+            Line::from("Repository: ".to_owned() + &package.repo),
+            Line::from("Version: ".to_owned() + &package.version.to_string()),
+            Line::from("Description: ".to_owned() + &package.description),
+            Line::from("Depends On: ".to_owned() + &join_vec(package.depends_on)),
+            Line::from("Download size: ".to_owned() + &format_size(package.download_size)),
+            Line::from("Installed size: ".to_owned() + &format_size(package.installed_size)),
+            Line::from("Installed: ".to_owned() + if package.installed { "Yes" } else { "No" }),
         ];
 
         let details_display = Paragraph::new(details_text)
             .block(details_block)
             .wrap(Wrap { trim: false });
 
-        frame.render_stateful_widget(list, layout[0], &mut self.packages_list.state);
+        frame.render_stateful_widget(list, layout[0], &mut self.sync_list.state);
         frame.render_widget(details_display, layout[1]);
     }
 
-    pub fn render_filter_popup(&mut self, frame: &mut Frame<'_>) {
+    pub fn render_updates(&mut self, frame: &mut Frame<'_>, chunk: Rect) {
+        let layout = Layout::default()
+            .direction(Direction::Horizontal)
+            .constraints([Constraint::Percentage(30), Constraint::Percentage(70)].as_ref())
+            .split(chunk);
+
+        let download_size: u64 = self
+            .updates_list
+            .items
+            .iter()
+            .map(|u| u.download_size)
+            .sum();
         let block = Block::default()
-            .title("Filter by name")
-            .borders(Borders::ALL);
+            .borders(Borders::ALL)
+            .style(Style::default().fg(Color::White))
+            .title(format!(
+                "Updates ({}, {} to download)",
+                self.updates_list.items.len(),
+                format_size(download_size)
+            ))
+            .border_type(BorderType::Plain);
+
+        let items: Vec<_> = self
+            .updates_list
+            .filtered_items
+            .iter()
+            .map(|u| {
+                let marker = if self
+                    .updates_list
+                    .marked
+                    .contains(&format!("{}/{}", u.repo, u.name))
+                {
+                    "● "
+                } else {
+                    "  "
+                };
+
+                ListItem::new(Line::from(vec![
+                    Span::styled(marker, Style::default().fg(Color::Yellow)),
+                    Span::styled(u.name.clone(), Style::default().fg(self.config.theme.fg)),
+                    Span::styled(
+                        " ".to_owned() + &u.old_version.to_string(),
+                        Style::default().fg(Color::Red),
+                    ),
+                    Span::raw(" → "),
+                    Span::styled(u.new_version.to_string(), Style::default().fg(Color::Green)),
+                ]))
+            })
+            .collect();
+
+        let list = List::new(items)
+            .block(block)
+            .highlight_style(
+                Style::default()
+                    .fg(self.config.theme.selected_fg)
+                    .bg(self.config.theme.selected_bg)
+                    .add_modifier(Modifier::BOLD)
+                    .add_modifier(Modifier::REVERSED),
+            )
+            .highlight_symbol(">")
+            .highlight_spacing(HighlightSpacing::Always);
+
+        let mut details_text = vec![];
+        let mut title = "Details".to_string();
+        if let Some(update) = self.updates_list.selected() {
+            title = update.name.clone() + " Details";
+            details_text = vec![
+                Line::from("Repository: ".to_owned() + &update.repo),
+                Line::from(format!(
+                    "Version: {} → {}",
+                    update.old_version, update.new_version
+                )),
+                Line::from("Download size: ".to_owned() + &format_size(update.download_size)),
+                Line::from(
+                    "Installed size change: ".to_owned()
+                        + &update
+                            .installed_size_change()
+                            .map(format_size_change)
+                            .unwrap_or("...".to_string()),
+                ),
+            ];
+        }
+        let details_block = Block::default()
+            .borders(Borders::ALL)
+            .style(Style::default().fg(Color::White))
+            .title(title)
+            .border_type(BorderType::Plain);
+
+        let details_display = Paragraph::new(details_text)
+            .block(details_block)
+            .wrap(Wrap { trim: false });
+
+        frame.render_stateful_widget(list, layout[0], &mut self.updates_list.state);
+        frame.render_widget(details_display, layout[1]);
+    }
+
+    pub fn render_filter_popup(&mut self, frame: &mut Frame<'_>) {
+        // The history is searched for plain text, not with a `Query`, and isn't ranked.
+        let history = self.active_menu_item == MenuItem::History;
+        let order = if self.config.rank_search_by_score {
+            "best matches first"
+        } else {
+            "list order"
+        };
+        let title = if history {
+            "Search".to_string()
+        } else {
+            format!("Search ({}, Tab to change)", order)
+        };
+        let block = Block::default().title(title).borders(Borders::ALL);
         let area = centered_rect(60, 20, frame.size());
 
-        let input = Paragraph::new(self.filter_input.value())
+        // Below the input, why the search doesn't parse or what it's still waiting for.
+        let status = match Query::parse(self.filter_input.value()) {
+            _ if history => Line::default(),
+            Err(error) => Line::styled(error, Style::default().fg(Color::Red)),
+            Ok(query)
+                if (query.needs_details() && !self.pending_details.is_empty())
+                    || (query.needs_files() && self.loading_files)
+                    || (query.needs_repo() && self.loading_sync_packages) =>
+            {
+                Line::styled("Loading...", Style::default().add_modifier(Modifier::DIM))
+            }
+            Ok(_) => Line::default(),
+        };
+        let input = Paragraph::new(vec![Line::raw(self.filter_input.value()), status])
             .style(Style::default())
             .block(block);
 
@@ -172,17 +962,531 @@ impl App {
         frame.render_widget(Clear, area); //this clears out the background
         frame.render_widget(input, area);
     }
+
+    pub fn render_transaction_popup(&mut self, frame: &mut Frame<'_>) {
+        let Some(transaction) = &self.transaction else {
+            return;
+        };
+
+        let block = Block::default()
+            .title(transaction.title.clone())
+            .borders(Borders::ALL);
+        let area = centered_rect(60, 60, frame.size());
+
+        let mut lines = vec![Line::from(transaction.command.join(" ")), Line::from("")];
+        for note in &transaction.notes {
+            lines.push(Line::styled(
+                note.clone(),
+                Style::default().fg(Color::Yellow),
+            ));
+            lines.push(Line::from(""));
+        }
+        match &transaction.packages {
+            None => lines.push(Line::from("Checking what would change...")),
+            Some(Ok(packages)) => {
+                lines.push(Line::from(format!("{} packages:", packages.len())));
+                lines.extend(packages.iter().map(|p| {
+                    // Highlight anything that wasn't picked, like cascading dependencies.
+                    let name = p.split(' ').next().unwrap_or_default();
+                    let style = if transaction.targets.iter().any(|t| t == name) {
+                        Style::default()
+                    } else {
+                        Style::default().fg(Color::Yellow)
+                    };
+                    Line::styled("  ".to_owned() + p, style)
+                }));
+                lines.push(Line::from(""));
+                lines.push(Line::from("Proceed? y/n"));
+            }
+            Some(Err(e)) => {
+                lines.push(Line::styled(e.clone(), Style::default().fg(Color::Red)));
+                lines.push(Line::from(""));
+                lines.push(Line::from("Press n or Esc to go back"));
+            }
+        }
+
+        let popup = Paragraph::new(lines)
+            .block(block)
+            .wrap(Wrap { trim: false });
+
+        frame.render_widget(Clear, area);
+        frame.render_widget(popup, area);
+    }
+
+    pub fn render_orphan_cleanup(&mut self, frame: &mut Frame<'_>) {
+        let Some(cleanup) = self.orphan_cleanup.as_mut() else {
+            return;
+        };
+        let area = centered_rect(60, 60, frame.size());
+
+        if cleanup.orphans.is_empty() {
+            let para = Paragraph::new("There are no orphans to clean up. Press Esc to go back.")
+                .block(
+                    Block::default()
+                        .title("Clean orphans")
+                        .borders(Borders::ALL),
+                );
+            frame.render_widget(Clear, area);
+            frame.render_widget(para, area);
+            return;
+        }
+
+        let freed: u64 = cleanup
+            .orphans
+            .iter()
+            .filter(|(name, _)| !cleanup.keep.contains(name))
+            .map(|(_, size)| size)
+            .sum();
+        let block = Block::default()
+            .title(format!(
+                "Clean orphans - {} freed (space to keep/remove, Enter to continue, Esc to cancel)",
+                format_size(freed)
+            ))
+            .borders(Borders::ALL);
+
+        let items: Vec<_> = cleanup
+            .orphans
+            .iter()
+            .map(|(name, size)| {
+                let tick = if cleanup.keep.contains(name) {
+                    "[ ] "
+                } else {
+                    "[x] "
+                };
+                ListItem::new(Line::from(vec![
+                    Span::raw(tick),
+                    Span::raw(format!("{:<40}", name)),
+                    Span::raw(format_size(*size)),
+                ]))
+            })
+            .collect();
+
+        let list = List::new(items).block(block).highlight_style(
+            Style::default()
+                .fg(self.config.theme.selected_fg)
+                .bg(self.config.theme.selected_bg),
+        );
+
+        frame.render_widget(Clear, area);
+        frame.render_stateful_widget(list, area, &mut cleanup.state);
+    }
+
+    pub fn render_history(&mut self, frame: &mut Frame<'_>, chunk: Rect) {
+        if self.show_transactions {
+            return self.render_transactions(frame, chunk);
+        }
+
+        let block = Block::default()
+            .borders(Borders::ALL)
+            .style(Style::default().fg(Color::White))
+            .title(filters_title(
+                &format!("History - {}", self.history_kind.label()),
+                &self.history_list.filters,
+            ))
+            .border_type(BorderType::Plain);
+
+        let items: Vec<_> = self
+            .history_list
+            .filtered_items
+            .iter()
+            .map(|e| ListItem::new(history_line(e)))
+            .collect();
+
+        let list = List::new(items)
+            .block(block)
+            .highlight_style(
+                Style::default()
+                    .fg(self.config.theme.selected_fg)
+                    .bg(self.config.theme.selected_bg)
+                    .add_modifier(Modifier::BOLD)
+                    .add_modifier(Modifier::REVERSED),
+            )
+            .highlight_symbol(">")
+            .highlight_spacing(HighlightSpacing::Always);
+
+        frame.render_stateful_widget(list, chunk, &mut self.history_list.state);
+    }
+
+    /// Each transaction with its package changes below it.
+    fn render_transactions(&mut self, frame: &mut Frame<'_>, chunk: Rect) {
+        let block = Block::default()
+            .borders(Borders::ALL)
+            .style(Style::default().fg(Color::White))
+            .title(filters_title(
+                "History - transactions",
+                &self.history_list.filters,
+            ))
+            .border_type(BorderType::Plain);
+
+        let items: Vec<_> = self
+            .history_transactions
+            .filtered_items
+            .iter()
+            .map(|t| ListItem::new(transaction_lines(t)))
+            .collect();
+
+        let list = List::new(items)
+            .block(block)
+            .highlight_style(
+                Style::default()
+                    .fg(self.config.theme.selected_fg)
+                    .bg(self.config.theme.selected_bg)
+                    .add_modifier(Modifier::BOLD),
+            )
+            .highlight_symbol(">")
+            .highlight_spacing(HighlightSpacing::Always);
+
+        frame.render_stateful_widget(list, chunk, &mut self.history_transactions.state);
+    }
+
+    /// The cached packages on the left, the files of the selected one on the right. Packages
+    /// that aren't installed are highlighted.
+    pub fn render_cache(&mut self, frame: &mut Frame<'_>, chunk: Rect) {
+        let installed = self.installed_versions();
+        let chunks = Layout::default()
+            .direction(Direction::Horizontal)
+            .constraints([Constraint::Percentage(40), Constraint::Percentage(60)])
+            .split(chunk);
+
+        let groups = &self.cache_list.items;
+        let total: u64 = groups.iter().map(|g| g.size()).sum();
+        let files: usize = groups.iter().map(|g| g.packages.len()).sum();
+        let block = Block::default()
+            .borders(Borders::ALL)
+            .style(Style::default().fg(Color::White))
+            .title(format!(
+                "Cache - {} in {} files, keeping {} versions",
+                format_size(total),
+                files,
+                self.cache_keep
+            ))
+            .border_type(BorderType::Plain);
+
+        let uninstalled = Style::default()
+            .fg(self.config.theme.orphan_bg)
+            .add_modifier(Modifier::DIM);
+        let items: Vec<_> = groups
+            .iter()
+            .map(|g| {
+                let style = if installed.contains_key(&g.name) {
+                    Style::default()
+                } else {
+                    uninstalled
+                };
+                ListItem::new(Line::styled(
+                    format!(
+                        "{:<30} {:>3} {:>11}",
+                        g.name,
+                        g.packages.len(),
+                        format_size(g.size())
+                    ),
+                    style,
+                ))
+            })
+            .collect();
+
+        let list = List::new(items)
+            .block(block)
+            .highlight_style(
+                Style::default()
+                    .fg(self.config.theme.selected_fg)
+                    .bg(self.config.theme.selected_bg)
+                    .add_modifier(Modifier::BOLD),
+            )
+            .highlight_symbol(">")
+            .highlight_spacing(HighlightSpacing::Always);
+        frame.render_stateful_widget(list, chunks[0], &mut self.cache_list.state);
+
+        let Some(group) = self.cache_list.selected() else {
+            return;
+        };
+        let installed = installed.get(&group.name);
+        let lines: Vec<Line> = group
+            .packages
+            .iter()
+            .rev()
+            .map(|p| {
+                let mut spans = vec![
+                    Span::raw(format!("{:<24}", p.version.to_string())),
+                    Span::raw(format!("{:>11}  ", format_size(p.size))),
+                    Span::raw(p.path.to_string_lossy().to_string()),
+                ];
+                match installed {
+                    Some(version) if *version == p.version => spans.push(Span::styled(
+                        " (installed)",
+                        Style::default().fg(Color::Green),
+                    )),
+                    Some(_) => {}
+                    None => spans.iter_mut().for_each(|s| s.style = uninstalled),
+                }
+                Line::from(spans)
+            })
+            .collect();
+
+        let title = match installed {
+            Some(version) => format!("{} - {} installed", group.name, version),
+            None => format!("{} - not installed", group.name),
+        };
+        let files = Paragraph::new(lines).block(
+            Block::default()
+                .borders(Borders::ALL)
+                .style(Style::default().fg(Color::White))
+                .title(title),
+        );
+        frame.render_widget(files, chunks[1]);
+    }
+
+    pub fn render_sort_menu(&mut self, frame: &mut Frame<'_>) {
+        let area = centered_rect(40, 40, frame.size());
+        let block = Block::default()
+            .title("Sort by (Enter to pick, again to reverse, Esc to cancel)")
+            .borders(Borders::ALL);
+
+        let items: Vec<_> = SortOrder::ALL
+            .iter()
+            .map(|order| {
+                let current = if *order == self.sort_order {
+                    self.sort_arrow()
+                } else {
+                    " "
+                };
+                ListItem::new(format!("{} {}", current, order.label()))
+            })
+            .collect();
+
+        let list = List::new(items).block(block).highlight_style(
+            Style::default()
+                .fg(self.config.theme.selected_fg)
+                .bg(self.config.theme.selected_bg),
+        );
+
+        frame.render_widget(Clear, area);
+        frame.render_stateful_widget(list, area, &mut self.sort_menu);
+    }
+
+    fn sort_arrow(&self) -> &'static str {
+        if self.sort_descending {
+            "▼"
+        } else {
+            "▲"
+        }
+    }
+
+    pub fn render_dependency_tree(&mut self, frame: &mut Frame<'_>) {
+        let area = centered_rect(70, 80, frame.size());
+        let Some(tree) = self.dependency_tree.as_mut() else {
+            return;
+        };
+        let Some(graph) = &self.dependency_graph else {
+            let para = Paragraph::new("Working out the dependencies...").block(
+                Block::default()
+                    .title(tree.root.clone())
+                    .borders(Borders::ALL),
+            );
+            frame.render_widget(Clear, area);
+            frame.render_widget(para, area);
+            return;
+        };
+
+        let title = if tree.reverse {
+            "Required by "
+        } else {
+            "Dependencies of "
+        };
+        let block = Block::default()
+            .title(format!(
+                "{}{} (l/h to expand/collapse, r to reverse, Enter to jump, Esc to close)",
+                title, tree.root
+            ))
+            .borders(Borders::ALL);
+
+        let dim = Style::default().add_modifier(Modifier::DIM);
+        let items: Vec<_> = tree
+            .rows
+            .iter()
+            .map(|row| {
+                let marker = if !row.has_children {
+                    "  "
+                } else if tree.expanded.contains(&row.path) {
+                    "▾ "
+                } else {
+                    "▸ "
+                };
+                let style = match graph.package_type(row.name()) {
+                    Some(PackageType::Explicit) | Some(PackageType::Foreign) => {
+                        Style::default().add_modifier(Modifier::BOLD)
+                    }
+                    Some(_) => Style::default(),
+                    None => Style::default().fg(Color::Red),
+                };
+
+                let mut spans = vec![
+                    Span::raw("  ".repeat(row.depth) + marker),
+                    Span::styled(row.name().to_string(), style),
+                ];
+                if let Some(provides) = &row.link.provides {
+                    spans.push(Span::styled(format!(" (provides {})", provides), dim));
+                }
+                if row.link.optional {
+                    spans.push(Span::styled(" (optional)", dim));
+                }
+                if !row.link.installed {
+                    spans.push(Span::styled(
+                        " (not installed)",
+                        Style::default().fg(Color::Red),
+                    ));
+                }
+                if row.cycle {
+                    spans.push(Span::styled(" ↻ cycle", Style::default().fg(Color::Yellow)));
+                }
+                ListItem::new(Line::from(spans))
+            })
+            .collect();
+
+        let list = List::new(items).block(block).highlight_style(
+            Style::default()
+                .fg(self.config.theme.selected_fg)
+                .bg(self.config.theme.selected_bg),
+        );
+
+        frame.render_widget(Clear, area);
+        frame.render_stateful_widget(list, area, &mut tree.state);
+    }
+
+    pub fn render_command_log(&mut self, frame: &mut Frame<'_>) {
+        let title = if self.command_output.is_some() {
+            "Running..."
+        } else {
+            "Finished, press Esc to close"
+        };
+        let block = Block::default().title(title).borders(Borders::ALL);
+        let area = centered_rect(80, 80, frame.size());
+
+        // Keep the latest output in view.
+        let height = area.height.saturating_sub(2) as usize;
+        let skip = self.command_log.len().saturating_sub(height);
+        let lines: Vec<Line> = self
+            .command_log
+            .iter()
+            .skip(skip)
+            .map(|l| Line::from(l.clone()))
+            .collect();
+
+        let log = Paragraph::new(lines).block(block);
+
+        frame.render_widget(Clear, area);
+        frame.render_widget(log, area);
+    }
+}
+
+pub fn render_empty_list(
+    frame: &mut Frame<'_>,
+    chunk: Rect,
+    filters: &[ListFilter],
+    tab: MenuItem,
+) {
+    // Filters can only be removed one by one from the package list.
+    let remove_one = if tab == MenuItem::PackageList {
+        ", or Backspace or a number to remove one"
+    } else {
+        ""
+    };
+    let para = Paragraph::new(format!(
+        "Could not find any packages that match the filters{}. Press a to clear them{}.",
+        filters_title("", filters),
+        remove_one,
+    ))
+    .style(Style::default());
+
+    frame.render_widget(para, chunk);
 }
 
-pub fn render_empty_list(frame: &mut Frame<'_>, chunk: Rect) {
+pub fn render_loading(frame: &mut Frame<'_>, chunk: Rect, text: &str) {
+    let para = Paragraph::new(text.to_string()).style(Style::default().add_modifier(Modifier::DIM));
+
+    frame.render_widget(para, chunk);
+}
+
+pub fn render_no_updates(frame: &mut Frame<'_>, chunk: Rect) {
     let para = Paragraph::new(
-        "Could not find any packages that match the filter. Please reset it or try another.",
+        "Everything is up to date according to the local package databases. Press A to sync them and upgrade.",
     )
     .style(Style::default());
 
     frame.render_widget(para, chunk);
 }
 
+/// e.g. `2024-04-02 10:00 upgraded pacman 6.0.2-9 -> 6.1.0-3`, colored by what happened.
+fn history_line(event: &HistoryEvent) -> Line<'static> {
+    let mut spans = vec![Span::raw(format_date(event.timestamp) + " ")];
+    spans.extend(event_spans(&event.kind));
+    Line::from(spans)
+}
+
+/// e.g. `2024-04-02 10:00 pacman -Syu` followed by the changes it made, indented.
+fn transaction_lines(transaction: &PastTransaction) -> Text<'static> {
+    let mut header = vec![Span::raw(format_date(transaction.timestamp) + " ")];
+    header.push(Span::styled(
+        transaction
+            .command
+            .clone()
+            .unwrap_or("unknown command".to_string()),
+        Style::default().add_modifier(Modifier::BOLD),
+    ));
+    if !transaction.completed {
+        header.push(Span::styled(" (failed)", Style::default().fg(Color::Red)));
+    }
+
+    let mut lines = vec![Line::from(header)];
+    lines.extend(transaction.changes.iter().map(|change| {
+        let mut spans = vec![Span::raw("    ")];
+        spans.extend(event_spans(&change.kind));
+        Line::from(spans)
+    }));
+    Text::from(lines)
+}
+
+fn event_spans(kind: &EventKind) -> Vec<Span<'static>> {
+    let color = match kind {
+        EventKind::Installed { .. } => Color::Green,
+        EventKind::Upgraded { .. } => Color::Cyan,
+        EventKind::Downgraded { .. } => Color::Yellow,
+        EventKind::Removed { .. } | EventKind::TransactionFailed => Color::Red,
+        EventKind::Reinstalled { .. } => Color::Blue,
+        EventKind::Warning(_) => Color::Magenta,
+        EventKind::TransactionStarted
+        | EventKind::TransactionCompleted
+        | EventKind::Command(_)
+        | EventKind::Hook(_) => Color::DarkGray,
+    };
+    vec![
+        Span::styled(format!("{:<12}", kind.action()), Style::default().fg(color)),
+        Span::raw(kind.subject()),
+    ]
+}
+
+/// `title` followed by the filter chain, numbered so each term can be removed with its number.
+fn filters_title(title: &str, filters: &[ListFilter]) -> String {
+    filters
+        .iter()
+        .enumerate()
+        .fold(title.to_string(), |title, (i, f)| {
+            format!("{} [{} {}]", title, i + 1, f.label())
+        })
+}
+
+/// Highlights the characters of `name` matching the search, if there is one.
+fn highlight_name(search: Option<&FuzzySearch>, name: &str, style: Style) -> Vec<Span<'static>> {
+    match search {
+        Some(search) => search.highlight(
+            name,
+            style,
+            style.add_modifier(Modifier::BOLD | Modifier::UNDERLINED),
+        ),
+        None => vec![Span::styled(name.to_string(), style)],
+    }
+}
+
 /// helper function to create a centered rect using up certain percentage of the available rect `r`
 fn centered_rect(percent_x: u16, percent_y: u16, r: Rect) -> Rect {
     let popup_layout = Layout::vertical([
@@ -200,6 +1504,14 @@ fn centered_rect(percent_x: u16, percent_y: u16, r: Rect) -> Rect {
     .split(popup_layout[1])[1]
 }
 
+/// Copies `text` with the OSC 52 escape sequence, which the terminal passes on to the clipboard.
+/// Works over ssh too, as long as the terminal supports it.
+pub fn copy_to_clipboard(text: &str) -> io::Result<()> {
+    let mut out = stdout();
+    write!(out, "\x1b]52;c;{}\x07", STANDARD.encode(text))?;
+    out.flush()
+}
+
 fn join_vec(vec: Vec<String>) -> String {
     vec.iter()
         .map(|x| x.to_string() + ",")
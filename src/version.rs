@@ -0,0 +1,260 @@
+use std::{cmp::Ordering, fmt};
+
+/// A package version, `[epoch:]pkgver[-pkgrel]`.
+///
+/// Ordered like pacman's `vercmp`, except that a version without a release comes before the
+/// same version with any release so the order is total, e.g. for sorting. See `vercmp` for
+/// pacman's own comparison.
+#[derive(Clone, Debug, Default)]
+pub struct Version {
+    pub epoch: Option<String>,
+    pub pkgver: String,
+    pub pkgrel: Option<String>,
+}
+
+impl Version {
+    pub fn has_epoch(&self) -> bool {
+        self.epoch.is_some()
+    }
+}
+
+impl From<&str> for Version {
+    fn from(evr: &str) -> Self {
+        let evr = evr.trim();
+        let digits = evr.bytes().take_while(|c| c.is_ascii_digit()).count();
+        let (epoch, rest) = match evr[digits..].strip_prefix(':') {
+            Some(rest) if digits > 0 => (Some(evr[..digits].to_string()), rest),
+            Some(rest) => (None, rest),
+            None => (None, evr),
+        };
+
+        let (pkgver, pkgrel) = match rest.rsplit_once('-') {
+            Some((pkgver, pkgrel)) => (pkgver, Some(pkgrel.to_string())),
+            None => (rest, None),
+        };
+
+        Self {
+            epoch,
+            pkgver: pkgver.to_string(),
+            pkgrel,
+        }
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(epoch) = &self.epoch {
+            write!(f, "{}:", epoch)?;
+        }
+        write!(f, "{}", self.pkgver)?;
+        if let Some(pkgrel) = &self.pkgrel {
+            write!(f, "-{}", pkgrel)?;
+        }
+        Ok(())
+    }
+}
+
+impl Ord for Version {
+    fn cmp(&self, other: &Self) -> Ordering {
+        compare_epoch_and_pkgver(self, other).then_with(|| match (&self.pkgrel, &other.pkgrel) {
+            (Some(a), Some(b)) => rpmvercmp(a, b),
+            (a, b) => a.is_some().cmp(&b.is_some()),
+        })
+    }
+}
+
+impl PartialOrd for Version {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+// Equal in the total order, e.g. `1.01` and `1.1` are the same version but `1.0` and `1.0-1`
+// aren't.
+impl PartialEq for Version {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Version {}
+
+/// Compares two versions exactly like pacman's `vercmp`, where the release is only compared if
+/// both versions have one. That makes `1.0` equal to both `1.0-1` and `1.0-2`, so it's not a
+/// total order, unlike `Version::cmp`.
+pub fn vercmp(a: &Version, b: &Version) -> Ordering {
+    compare_epoch_and_pkgver(a, b).then_with(|| match (&a.pkgrel, &b.pkgrel) {
+        (Some(a), Some(b)) => rpmvercmp(a, b),
+        _ => Ordering::Equal,
+    })
+}
+
+fn compare_epoch_and_pkgver(a: &Version, b: &Version) -> Ordering {
+    let epoch = |v: &Version| v.epoch.clone().unwrap_or("0".to_string());
+    rpmvercmp(&epoch(a), &epoch(b)).then_with(|| rpmvercmp(&a.pkgver, &b.pkgver))
+}
+
+/// Compares two version segments, a port of `rpmvercmp` from libalpm.
+fn rpmvercmp(a: &str, b: &str) -> Ordering {
+    if a == b {
+        return Ordering::Equal;
+    }
+
+    let a = a.as_bytes();
+    let b = b.as_bytes();
+    // `one`/`two` are the start of the current segment, `end_one`/`end_two` the end of the last.
+    let (mut one, mut two) = (0, 0);
+    let (mut end_one, mut end_two) = (0, 0);
+
+    while one < a.len() && two < b.len() {
+        while one < a.len() && !a[one].is_ascii_alphanumeric() {
+            one += 1;
+        }
+        while two < b.len() && !b[two].is_ascii_alphanumeric() {
+            two += 1;
+        }
+
+        if one >= a.len() || two >= b.len() {
+            break;
+        }
+
+        // If the separator lengths were different, we are also finished.
+        if one - end_one != two - end_two {
+            return (one - end_one).cmp(&(two - end_two));
+        }
+
+        let is_num = a[one].is_ascii_digit();
+        let segment_end = |s: &[u8], start: usize| {
+            start
+                + s[start..]
+                    .iter()
+                    .take_while(|c| {
+                        if is_num {
+                            c.is_ascii_digit()
+                        } else {
+                            c.is_ascii_alphabetic()
+                        }
+                    })
+                    .count()
+        };
+        end_one = segment_end(a, one);
+        end_two = segment_end(b, two);
+
+        // Numeric segments are always newer than alpha segments.
+        if two == end_two {
+            return if is_num {
+                Ordering::Greater
+            } else {
+                Ordering::Less
+            };
+        }
+
+        let mut segment_one = &a[one..end_one];
+        let mut segment_two = &b[two..end_two];
+        if is_num {
+            while segment_one.first() == Some(&b'0') {
+                segment_one = &segment_one[1..];
+            }
+            while segment_two.first() == Some(&b'0') {
+                segment_two = &segment_two[1..];
+            }
+            // Whichever number has more digits wins.
+            if segment_one.len() != segment_two.len() {
+                return segment_one.len().cmp(&segment_two.len());
+            }
+        }
+
+        match segment_one.cmp(segment_two) {
+            Ordering::Equal => {}
+            ordering => return ordering,
+        }
+
+        one = end_one;
+        two = end_two;
+    }
+
+    // All the segments compared the same but the separators were different.
+    if one >= a.len() && two >= b.len() {
+        return Ordering::Equal;
+    }
+
+    // A remaining alpha segment never beats an empty string, e.g. 1.0alpha < 1.0 < 1.0.1.
+    let one_is_alpha = a.get(one).is_some_and(|c| c.is_ascii_alphabetic());
+    let two_is_alpha = b.get(two).is_some_and(|c| c.is_ascii_alphabetic());
+    if (one >= a.len() && !two_is_alpha) || one_is_alpha {
+        Ordering::Less
+    } else {
+        Ordering::Greater
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn orders_like_vercmp() {
+        use Ordering::*;
+        let cases = [
+            ("1.0", "1.0", Equal),
+            ("1.5.1", "1.5.0", Greater),
+            // Alpha parts are older than nothing, which is older than more numbers.
+            ("1.0a", "1.0", Less),
+            ("1.0alpha", "1.0beta", Less),
+            ("1.0", "1.0.1", Less),
+            ("1.0.0", "1.0", Greater),
+            ("1.5.a", "1.5.0", Less),
+            ("1.0.r12.gabc123", "1.0", Greater),
+            // Leading zeros don't count.
+            ("1.01", "1.1", Equal),
+            ("1.001", "1.1", Equal),
+            ("1.10", "1.9", Greater),
+            // The epoch beats everything else.
+            ("1:1.0", "2.0", Greater),
+            ("2:0.1", "1:99.0-9", Greater),
+            ("0:1.0", "1.0", Equal),
+            ("1:1.0-1", "1.0-99", Greater),
+            // The release is only compared when both have one.
+            ("1.0-1", "1.0-2", Less),
+            ("1.0-2", "1.0", Equal),
+            ("1.0", "1.0-1", Equal),
+            ("1.0-10", "1.0.1-1", Less),
+            // Longer separators win, otherwise separators don't matter.
+            ("1..0", "1.0", Greater),
+            ("1.0", "1_0", Equal),
+            ("1+0", "1.0", Equal),
+        ];
+
+        for (a, b, expected) in cases {
+            let (a, b) = (Version::from(a), Version::from(b));
+            assert_eq!(vercmp(&a, &b), expected, "{} vs {}", a, b);
+            assert_eq!(vercmp(&b, &a), expected.reverse(), "{} vs {}", b, a);
+        }
+    }
+
+    #[test]
+    fn orders_totally() {
+        let no_release = Version::from("1.0");
+        let first = Version::from("1.0-1");
+        let second = Version::from("1.0-2");
+
+        assert!(no_release < first && first < second && no_release < second);
+        assert!(no_release != first && no_release != second);
+        assert_eq!(Version::from("1.00-1"), first);
+        // Everything else still orders like vercmp.
+        assert!(Version::from("1:1.0") > Version::from("2.0-1"));
+        assert!(Version::from("1.0-10") < Version::from("1.0.1-1"));
+    }
+
+    #[test]
+    fn parses_epoch_version_and_release() {
+        let version = Version::from("2:1.0.r3-1");
+        assert_eq!(version.epoch.as_deref(), Some("2"));
+        assert_eq!(version.pkgver, "1.0.r3");
+        assert_eq!(version.pkgrel.as_deref(), Some("1"));
+        assert_eq!(version.to_string(), "2:1.0.r3-1");
+
+        let version = Version::from("20240101");
+        assert!(!version.has_epoch() && version.pkgrel.is_none());
+    }
+}
@@ -0,0 +1,39 @@
+use std::path::PathBuf;
+use std::sync::mpsc::{self, Receiver};
+
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+
+/// Watches the package database for changes made outside pacmanman, e.g. installing something
+/// in another terminal.
+pub struct DatabaseWatcher {
+    // Stops watching once dropped.
+    _watcher: RecommendedWatcher,
+    changes: Receiver<()>,
+}
+
+impl DatabaseWatcher {
+    /// Watches `paths`, see `PackageBackend::database_paths`. Paths that don't exist are skipped.
+    pub fn new(paths: &[PathBuf]) -> notify::Result<Self> {
+        let (sender, changes) = mpsc::channel();
+        let mut watcher =
+            notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+                if event.is_ok_and(|e| !e.kind.is_access()) {
+                    let _ = sender.send(());
+                }
+            })?;
+
+        for path in paths.iter().filter(|p| p.exists()) {
+            watcher.watch(path, RecursiveMode::NonRecursive)?;
+        }
+
+        Ok(Self {
+            _watcher: watcher,
+            changes,
+        })
+    }
+
+    /// Whether anything changed since the last call.
+    pub fn changed(&self) -> bool {
+        self.changes.try_iter().count() > 0
+    }
+}
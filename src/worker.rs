@@ -0,0 +1,144 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::mpsc::{self, Receiver, Sender, TryIter};
+use std::sync::Arc;
+use std::thread;
+
+use crate::backend::PackageBackend;
+use crate::commands::{PackageDetails, PackageVersionInfo, SyncPackage};
+use crate::files::{self, FileRow};
+use crate::graph::DependencyGraph;
+use crate::history::HistoryEvent;
+use crate::pkgcache::{self, CachedPackage};
+
+/// Anything that may spawn the package manager or read the whole database, so it has to run
+/// off the render loop.
+pub enum Job {
+    Packages,
+    SyncPackages,
+    Details(String),
+    Files,
+    /// Lays out the files of a package with their sizes, see `files::file_tree`. Takes the
+    /// package name, its files and its backup files.
+    FileTree(String, Vec<String>, Vec<String>),
+    History,
+    /// Scans the package cache directories.
+    PackageCache(Vec<PathBuf>),
+    /// Builds the graph from a snapshot of the package list.
+    Graph(Vec<PackageVersionInfo>),
+    /// Works out what a transaction would touch, tagged with the transaction's id.
+    Preview(usize, Preview),
+}
+
+pub enum Preview {
+    Install(Vec<String>),
+    Remove(Vec<String>),
+    Upgrade,
+}
+
+pub enum JobResult {
+    /// The packages along with the `PackageBackend::database_stamp` from before they were read.
+    Packages(Vec<PackageVersionInfo>, Option<u64>),
+    SyncPackages(Vec<SyncPackage>),
+    Details(String, Box<PackageDetails>),
+    Files(HashMap<String, Vec<String>>),
+    FileTree(String, Vec<FileRow>),
+    History(Result<Vec<HistoryEvent>, String>),
+    PackageCache(Vec<CachedPackage>),
+    Graph(DependencyGraph),
+    Preview(usize, Result<Vec<String>, String>),
+}
+
+/// Background threads running jobs one at a time, in the order they were sent.
+///
+/// Jobs the user is waiting on, see `Job::is_urgent`, have a thread of their own so they don't
+/// queue up behind everything else, e.g. the details of every package for a search.
+pub struct Worker {
+    jobs: Sender<Job>,
+    urgent_jobs: Sender<Job>,
+    results: Receiver<JobResult>,
+}
+
+impl Worker {
+    pub fn spawn(backend: Arc<dyn PackageBackend>) -> Self {
+        let (result_sender, results) = mpsc::channel();
+        let jobs = spawn_thread(backend.clone(), result_sender.clone());
+        let urgent_jobs = spawn_thread(backend, result_sender);
+
+        Self {
+            jobs,
+            urgent_jobs,
+            results,
+        }
+    }
+
+    pub fn send(&self, job: Job) {
+        let jobs = if job.is_urgent() {
+            &self.urgent_jobs
+        } else {
+            &self.jobs
+        };
+        // The threads only stop once the app is gone, so this can't fail.
+        let _ = jobs.send(job);
+    }
+
+    /// The results of every job that finished since the last call, without waiting.
+    pub fn results(&self) -> TryIter<'_, JobResult> {
+        self.results.try_iter()
+    }
+}
+
+impl Job {
+    /// Transaction previews, reloading the package list, scanning the package cache and the
+    /// files popup, the user can't do much until they're done.
+    fn is_urgent(&self) -> bool {
+        matches!(
+            self,
+            Job::Packages | Job::FileTree(..) | Job::PackageCache(_) | Job::Preview(..)
+        )
+    }
+}
+
+fn spawn_thread(backend: Arc<dyn PackageBackend>, results: Sender<JobResult>) -> Sender<Job> {
+    let (jobs, job_receiver) = mpsc::channel();
+    thread::spawn(move || {
+        for job in job_receiver {
+            let result = run(backend.as_ref(), job);
+            if results.send(result).is_err() {
+                break;
+            }
+        }
+    });
+    jobs
+}
+
+fn run(backend: &dyn PackageBackend, job: Job) -> JobResult {
+    match job {
+        Job::Packages => {
+            let stamp = backend.database_stamp();
+            JobResult::Packages(backend.list_packages(), stamp)
+        }
+        Job::SyncPackages => JobResult::SyncPackages(backend.list_sync_packages()),
+        Job::Details(name) => {
+            let details = backend.get_details(&name);
+            JobResult::Details(name, Box::new(details))
+        }
+        Job::Files => JobResult::Files(backend.list_files()),
+        Job::FileTree(name, paths, backup_files) => {
+            JobResult::FileTree(name, files::file_tree(&paths, &backup_files))
+        }
+        Job::History => JobResult::History(backend.history()),
+        Job::PackageCache(dirs) => JobResult::PackageCache(pkgcache::scan(&dirs)),
+        Job::Graph(mut packages) => {
+            JobResult::Graph(DependencyGraph::build(&mut packages, backend))
+        }
+        Job::Preview(id, preview) => JobResult::Preview(
+            id,
+            match preview {
+                Preview::Install(names) => backend.install_preview(&names),
+                Preview::Remove(names) => backend.remove_preview(&names),
+                Preview::Upgrade => backend.upgrade_preview(),
+            },
+        ),
+    }
+}